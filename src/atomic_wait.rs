@@ -0,0 +1,246 @@
+//! Futex-like waiting on an [AtomicU32], the single blocking primitive every sleeping lock and channel in this crate builds on.
+//! - [wait] puts the current thread to sleep, but only if the atomic still contains the expected value.
+//! - [wake_one] and [wake_all] wake threads waiting on the same atomic.
+//! - Spurious wake-ups are possible, so [wait] always needs to be called in a loop that checks the condition again.
+//!
+//! On Linux these are the futex syscalls from [crate::ch8].
+//! Everywhere else a thread waits in an address-keyed queue (like the parking_lot crate does) and sleeps with [thread::park].
+//! In a [crate::model] execution the model checker plays the role of the kernel.
+
+use super::*;
+
+/// Sleeps until woken up by [wake_one] or [wake_all], but only if `a` still contains `expected`.
+/// Might return spuriously, so call this in a loop.
+pub fn wait(a: &AtomicU32, expected: u32) {
+    #[cfg(feature = "model")]
+    if crate::model::futex_wait(a, expected, false).is_some() {
+        return;
+    }
+    #[cfg(target_os = "linux")]
+    crate::ch8::futex_wait(a, expected);
+    #[cfg(not(target_os = "linux"))]
+    parking::wait(a, expected);
+}
+
+/// Like [wait], but sleeps for at most `timeout`.
+/// Doesn't report whether it timed out: the caller has to check the time itself anyway because of spurious wake-ups.
+pub fn wait_timeout(a: &AtomicU32, expected: u32, timeout: Duration) {
+    #[cfg(feature = "model")]
+    if crate::model::futex_wait(a, expected, true).is_some() {
+        return;
+    }
+    #[cfg(target_os = "linux")]
+    crate::ch8::futex_wait_timeout(a, expected, timeout);
+    #[cfg(not(target_os = "linux"))]
+    parking::wait_timeout(a, expected, timeout);
+}
+
+/// Wakes at most one thread waiting on `a`.
+///
+/// `a` is never dereferenced, so it may be dangling.
+/// This allows waking a thread after the memory of the atomic might have been freed (by the woken thread).
+pub fn wake_one(a: *const AtomicU32) {
+    #[cfg(feature = "model")]
+    if crate::model::futex_wake(a, false).is_some() {
+        return;
+    }
+    #[cfg(target_os = "linux")]
+    crate::ch8::futex_wake_one(a);
+    #[cfg(not(target_os = "linux"))]
+    parking::wake_one(a);
+}
+
+/// Wakes every thread waiting on `a`. `a` may be dangling, see [wake_one].
+pub fn wake_all(a: *const AtomicU32) {
+    #[cfg(feature = "model")]
+    if crate::model::futex_wake(a, true).is_some() {
+        return;
+    }
+    #[cfg(target_os = "linux")]
+    crate::ch8::futex_wake_all(a);
+    #[cfg(not(target_os = "linux"))]
+    parking::wake_all(a);
+}
+
+/// The portable fallback. Always compiled, so it is tested on Linux too.
+#[cfg_attr(target_os = "linux", allow(dead_code))]
+mod parking {
+    use super::*;
+
+    /// A thread sleeping in [wait] on `address`.
+    struct Waiter {
+        address: usize,
+        thread: thread::Thread,
+    }
+
+    /// Waiters for every address that hashes to this bucket, in the order they started waiting.
+    struct Bucket {
+        waiters: Mutex<VecDeque<Waiter>>,
+    }
+
+    const BUCKET_COUNT: usize = 64;
+
+    static BUCKETS: [Bucket; BUCKET_COUNT] = [const {
+        Bucket {
+            waiters: Mutex::new(VecDeque::new()),
+        }
+    }; BUCKET_COUNT];
+
+    fn bucket(address: usize) -> &'static Bucket {
+        // fibonacci hashing. the low bits of an address are mostly zero because of alignment
+        let hash = address.wrapping_mul(0x9E37_79B9_7F4A_7C15_u64 as usize);
+        &BUCKETS[(hash >> (usize::BITS - BUCKET_COUNT.trailing_zeros())) % BUCKET_COUNT]
+    }
+
+    fn waiters(address: usize) -> MutexGuard<'static, VecDeque<Waiter>> {
+        // a panic while holding the bucket lock can't leave the queue in an invalid state
+        bucket(address)
+            .waiters
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn wait(a: &AtomicU32, expected: u32) {
+        park_if(a, expected, thread::park);
+    }
+
+    pub fn wait_timeout(a: &AtomicU32, expected: u32, timeout: Duration) {
+        park_if(a, expected, || thread::park_timeout(timeout));
+    }
+
+    fn park_if(a: &AtomicU32, expected: u32, park: impl FnOnce()) {
+        let address = a as *const AtomicU32 as usize;
+        let current_thread = thread::current();
+
+        {
+            let mut waiters = waiters(address);
+
+            // Checking the value while holding the bucket lock makes checking and going to sleep atomic:
+            // a waker changes the value *before* locking the bucket, so either we see the new value here,
+            // or the waker sees us in the queue.
+            if a.load(Relaxed) != expected {
+                return;
+            }
+
+            waiters.push_back(Waiter {
+                address,
+                thread: current_thread.clone(),
+            });
+        }
+
+        park();
+
+        // park can return spuriously (or because of an unrelated unpark, or a timeout).
+        // Leave the queue so a wake isn't wasted on a thread that isn't waiting anymore. The caller will check again.
+        waiters(address).retain(|waiter| waiter.thread.id() != current_thread.id());
+    }
+
+    pub fn wake_one(a: *const AtomicU32) {
+        let address = a as usize;
+        let mut waiters = waiters(address);
+
+        if let Some(index) = waiters.iter().position(|waiter| waiter.address == address) {
+            let waiter = waiters.remove(index).expect("index is in bounds");
+            waiter.thread.unpark();
+        }
+    }
+
+    pub fn wake_all(a: *const AtomicU32) {
+        let address = a as usize;
+        let mut waiters = waiters(address);
+
+        waiters.retain(|waiter| {
+            if waiter.address == address {
+                waiter.thread.unpark();
+            }
+            waiter.address != address
+        });
+    }
+
+    #[test]
+    fn parking_wait_and_wake() {
+        let a = AtomicU32::new(0);
+        let woken = AtomicU32::new(0);
+        let thread_count = 4;
+
+        thread::scope(|s| {
+            for _ in 0..thread_count {
+                s.spawn(|| {
+                    while a.load(Acquire) == 0 {
+                        wait(&a, 0);
+                    }
+                    woken.fetch_add(1, Relaxed);
+                });
+            }
+
+            // wait doesn't sleep when the value is not the expected value
+            wait(&a, 1);
+
+            thread::sleep(Duration::from_millis(100));
+            a.store(1, Release);
+            wake_all(&a);
+        });
+
+        assert_eq!(woken.load(Relaxed), thread_count);
+        // nobody was left in the queue
+        assert!(waiters(&a as *const AtomicU32 as usize).is_empty());
+    }
+
+    #[test]
+    fn parking_wait_timeout() {
+        let a = AtomicU32::new(0);
+        let start = Instant::now();
+
+        // nobody wakes us up
+        wait_timeout(&a, 0, Duration::from_millis(50));
+
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert!(waiters(&a as *const AtomicU32 as usize).is_empty());
+    }
+}
+
+#[test]
+fn wait_and_wake() {
+    let a = AtomicU32::new(0);
+
+    thread::scope(|s| {
+        s.spawn(|| {
+            thread::sleep(Duration::from_millis(100));
+            a.store(1, Relaxed);
+            wake_one(&a);
+        });
+
+        // wait doesn't sleep when the value is not the expected value
+        wait(&a, 1);
+
+        while a.load(Relaxed) == 0 {
+            wait(&a, 0);
+        }
+    });
+
+    assert_eq!(a.load(Relaxed), 1);
+}
+
+#[test]
+fn wake_all_wakes_every_waiter() {
+    let a = AtomicU32::new(0);
+    let woken = AtomicU32::new(0);
+    let thread_count = 8;
+
+    thread::scope(|s| {
+        for _ in 0..thread_count {
+            s.spawn(|| {
+                while a.load(Acquire) == 0 {
+                    wait(&a, 0);
+                }
+                woken.fetch_add(1, Relaxed);
+            });
+        }
+
+        thread::sleep(Duration::from_millis(100));
+        a.store(1, Release);
+        wake_all(&a);
+    });
+
+    assert_eq!(woken.load(Relaxed), thread_count);
+}
@@ -0,0 +1,654 @@
+//! Microbenchmarks for the locks and channels, with std only so they run anywhere.
+//! - [Bench] runs an operation on every thread: first for a warmup that isn't measured, then for the measured duration.
+//! - Every thread times its own operations, so a thread that got less of the CPU shows up in [Report::fairness].
+//! - [Scenario::Uncontended] gives every thread its own lock or channel, [Scenario::Contended] shares one between the threads.
+//! - A [Report] prints as text, or as [Report::to_csv] and [Report::to_json] for comparing runs.
+//!
+//! [spin_lock], [lock], [queue_locks], [simple_channel], [oneshot_channel], [channel] and [mpmc_channel] are the benchmarks.
+
+use std::{fmt, sync::Barrier};
+
+use super::*;
+use crate::{
+    ch4::{ClhLock, McsLock, SpinLock, SpinLockFlag, TicketLock},
+    ch5::{self, mpmc, OneshotChannel, SimpleChannel},
+    ch6::Arc,
+    ch9,
+    executor::block_on,
+};
+
+/// Whether the threads share what is measured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scenario {
+    /// Every thread has its own lock or channel. Measures the cost of the operations themselves.
+    Uncontended,
+    /// The threads share the lock or channel, or talk to each other through it.
+    Contended,
+}
+
+impl Scenario {
+    pub const ALL: [Self; 2] = [Self::Uncontended, Self::Contended];
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Uncontended => "uncontended",
+            Self::Contended => "contended",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|scenario| scenario.name().eq_ignore_ascii_case(name))
+    }
+}
+
+/// How long and on how many threads a benchmark runs.
+#[derive(Debug, Clone, Copy)]
+pub struct Bench {
+    threads: usize,
+    warmup: Duration,
+    duration: Duration,
+}
+
+impl Default for Bench {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bench {
+    /// 4 threads, 100ms of warmup and 1s of measuring.
+    pub const fn new() -> Self {
+        Self {
+            threads: 4,
+            warmup: Duration::from_millis(100),
+            duration: Duration::from_secs(1),
+        }
+    }
+
+    /// # Panics
+    /// - When `threads == 0`
+    pub const fn threads(self, threads: usize) -> Self {
+        assert!(threads > 0, "a benchmark needs at least one thread");
+        Self { threads, ..self }
+    }
+
+    /// How long the operation runs before the measuring starts, to fill caches and let the threads get going.
+    pub const fn warmup(self, warmup: Duration) -> Self {
+        Self { warmup, ..self }
+    }
+
+    pub const fn duration(self, duration: Duration) -> Self {
+        Self { duration, ..self }
+    }
+
+    /// Runs an operation on every thread, and measures it.
+    /// `setup` runs on every thread (with its index) and returns that thread's operation.
+    /// The operation does one operation and returns its latency, so it can leave its own setup out of the latency.
+    pub fn run<S, F>(&self, name: &str, scenario: Scenario, setup: S) -> Report
+    where
+        S: Fn(usize) -> F + Sync,
+        F: FnMut() -> Duration,
+    {
+        // so every thread is done with its setup before any thread starts its warmup
+        let start = Barrier::new(self.threads);
+        let threads = thread::scope(|s| {
+            let handles: Vec<_> = (0..self.threads)
+                .map(|thread| {
+                    let (setup, start) = (&setup, &start);
+                    s.spawn(move || {
+                        let mut operation = setup(thread);
+                        start.wait();
+
+                        let warmup_end = Instant::now() + self.warmup;
+                        while Instant::now() < warmup_end {
+                            operation();
+                        }
+
+                        let mut report = ThreadReport {
+                            operations: 0,
+                            elapsed: Duration::ZERO,
+                            latencies: Latencies::new(),
+                        };
+                        let start = Instant::now();
+                        let end = start + self.duration;
+                        while Instant::now() < end {
+                            report.latencies.record(operation());
+                            report.operations += 1;
+                        }
+                        report.elapsed = start.elapsed();
+                        report
+                    })
+                })
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+
+        Report {
+            name: name.to_string(),
+            scenario,
+            threads,
+        }
+    }
+}
+
+/// Latencies, bucketed by their number of nanoseconds: 4 buckets for every power of two.
+/// A percentile is the upper bound of its bucket, so it is at most 25% too high.
+#[derive(Debug, Clone)]
+pub struct Latencies {
+    buckets: Vec<u64>,
+}
+
+impl Default for Latencies {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Latencies {
+    const SUB_BUCKETS: u32 = 4;
+
+    pub fn new() -> Self {
+        Self {
+            buckets: vec![0; (64 * Self::SUB_BUCKETS) as usize],
+        }
+    }
+
+    fn bucket(nanos: u64) -> usize {
+        if nanos < Self::SUB_BUCKETS as u64 {
+            return nanos as usize;
+        }
+        let exponent = 63 - nanos.leading_zeros();
+        // the two bits after the highest one
+        let sub_bucket = (nanos >> (exponent - 2)) & (Self::SUB_BUCKETS as u64 - 1);
+        (exponent * Self::SUB_BUCKETS) as usize + sub_bucket as usize
+    }
+
+    fn upper_bound(bucket: usize) -> u64 {
+        let sub_buckets = Self::SUB_BUCKETS as usize;
+        if bucket < sub_buckets {
+            return bucket as u64;
+        }
+        let exponent = bucket / sub_buckets;
+        let sub_bucket = (bucket % sub_buckets) as u64;
+        ((Self::SUB_BUCKETS as u64 + sub_bucket + 1) << (exponent - 2)) - 1
+    }
+
+    pub fn record(&mut self, latency: Duration) {
+        let nanos = u64::try_from(latency.as_nanos()).unwrap_or(u64::MAX);
+        self.buckets[Self::bucket(nanos)] += 1;
+    }
+
+    pub fn merge(&mut self, other: &Self) {
+        for (bucket, count) in self.buckets.iter_mut().zip(&other.buckets) {
+            *bucket += count;
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// `percentile` is between 0 and 100. [Duration::ZERO] when nothing was recorded.
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        let rank = (self.count() as f64 * percentile / 100.0).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Duration::from_nanos(Self::upper_bound(bucket));
+            }
+        }
+        Duration::ZERO
+    }
+}
+
+/// What a single thread measured.
+#[derive(Debug, Clone)]
+pub struct ThreadReport {
+    pub operations: u64,
+    /// How long the thread was measuring. Every thread stops at its own deadline, so this differs a little between threads.
+    pub elapsed: Duration,
+    pub latencies: Latencies,
+}
+
+/// What every thread of a benchmark measured.
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub name: String,
+    pub scenario: Scenario,
+    pub threads: Vec<ThreadReport>,
+}
+
+impl Report {
+    /// The percentiles in the text, CSV and JSON output.
+    pub const PERCENTILES: [f64; 4] = [50.0, 90.0, 99.0, 99.9];
+
+    /// The header line for [Report::to_csv].
+    pub const CSV_HEADER: &'static str =
+        "name,scenario,threads,operations,throughput,p50_ns,p90_ns,p99_ns,p99.9_ns,fairness";
+
+    pub fn operations(&self) -> u64 {
+        self.threads.iter().map(|t| t.operations).sum()
+    }
+
+    /// Operations per second, of all threads together.
+    pub fn throughput(&self) -> f64 {
+        self.threads
+            .iter()
+            .map(|t| t.operations as f64 / t.elapsed.as_secs_f64().max(f64::MIN_POSITIVE))
+            .sum()
+    }
+
+    /// The latencies of every thread together.
+    pub fn latencies(&self) -> Latencies {
+        let mut latencies = Latencies::new();
+        for thread in &self.threads {
+            latencies.merge(&thread.latencies);
+        }
+        latencies
+    }
+
+    /// Jain's fairness index: 1 when every thread did as many operations, `1 / threads` when a single thread did them all.
+    pub fn fairness(&self) -> f64 {
+        let sum: f64 = self.threads.iter().map(|t| t.operations as f64).sum();
+        let sum_of_squares: f64 = self
+            .threads
+            .iter()
+            .map(|t| (t.operations as f64).powi(2))
+            .sum();
+        match sum_of_squares {
+            0.0 => 1.0,
+            _ => sum * sum / (self.threads.len() as f64 * sum_of_squares),
+        }
+    }
+
+    /// A line of comma separated values, in the order of [Report::CSV_HEADER]. Without a line break.
+    pub fn to_csv(&self) -> String {
+        let latencies = self.latencies();
+        let mut csv = format!(
+            "{},{},{},{},{:.0}",
+            self.name,
+            self.scenario.name(),
+            self.threads.len(),
+            self.operations(),
+            self.throughput()
+        );
+        for percentile in Self::PERCENTILES {
+            csv += &format!(",{}", latencies.percentile(percentile).as_nanos());
+        }
+        csv + &format!(",{:.3}", self.fairness())
+    }
+
+    /// A JSON object, with the same fields as [Report::to_csv] plus the operations and time of every thread.
+    pub fn to_json(&self) -> String {
+        let latencies = self.latencies();
+        let percentiles: Vec<String> = Self::PERCENTILES
+            .iter()
+            .map(|&p| format!("\"p{p}\":{}", latencies.percentile(p).as_nanos()))
+            .collect();
+        let threads: Vec<String> = self
+            .threads
+            .iter()
+            .map(|t| {
+                format!(
+                    "{{\"operations\":{},\"elapsed_ns\":{}}}",
+                    t.operations,
+                    t.elapsed.as_nanos()
+                )
+            })
+            .collect();
+        format!(
+            "{{\"name\":\"{}\",\"scenario\":\"{}\",\"threads\":{},\"operations\":{},\"throughput\":{:.0},\"latency_ns\":{{{}}},\"fairness\":{:.3},\"per_thread\":[{}]}}",
+            self.name.replace('\\', "\\\\").replace('"', "\\\""),
+            self.scenario.name(),
+            self.threads.len(),
+            self.operations(),
+            self.throughput(),
+            percentiles.join(","),
+            self.fairness(),
+            threads.join(",")
+        )
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let operations = self.threads.iter().map(|t| t.operations);
+        let min = operations.clone().min().unwrap_or(0);
+        let max = operations.max().unwrap_or(0);
+        writeln!(
+            f,
+            "{}, {}, {} threads",
+            self.name,
+            self.scenario.name(),
+            self.threads.len()
+        )?;
+        writeln!(
+            f,
+            "throughput: {:.0} ops/s ({} ops)",
+            self.throughput(),
+            self.operations()
+        )?;
+        let latencies = self.latencies();
+        write!(f, "latency:   ")?;
+        for percentile in Self::PERCENTILES {
+            write!(f, " p{percentile} {:?}", latencies.percentile(percentile))?;
+        }
+        writeln!(f)?;
+        writeln!(
+            f,
+            "fairness:   {:.3} (ops per thread: min {min}, max {max})",
+            self.fairness()
+        )
+    }
+}
+
+/// The locks [lock] compares.
+pub trait Lock: Sync {
+    const NAME: &'static str;
+    fn new() -> Self;
+    /// Locks, increments the counter, and unlocks.
+    fn increment(&self);
+}
+
+impl Lock for SpinLock<u64> {
+    const NAME: &'static str = "SpinLock";
+    fn new() -> Self {
+        SpinLock::new(0)
+    }
+    fn increment(&self) {
+        *self.lock().unwrap() += 1;
+    }
+}
+
+/// [SpinLockFlag] doesn't protect a value, so this only locks and unlocks.
+impl Lock for SpinLockFlag {
+    const NAME: &'static str = "SpinLockFlag";
+    fn new() -> Self {
+        SpinLockFlag::new()
+    }
+    fn increment(&self) {
+        self.lock();
+        self.unlock();
+    }
+}
+
+impl Lock for TicketLock<u64> {
+    const NAME: &'static str = "TicketLock";
+    fn new() -> Self {
+        TicketLock::new(0)
+    }
+    fn increment(&self) {
+        *self.lock().unwrap() += 1;
+    }
+}
+
+impl Lock for McsLock<u64> {
+    const NAME: &'static str = "McsLock";
+    fn new() -> Self {
+        McsLock::new(0)
+    }
+    fn increment(&self) {
+        *self.lock().unwrap() += 1;
+    }
+}
+
+impl Lock for ClhLock<u64> {
+    const NAME: &'static str = "ClhLock";
+    fn new() -> Self {
+        ClhLock::new(0)
+    }
+    fn increment(&self) {
+        *self.lock().unwrap() += 1;
+    }
+}
+
+impl Lock for ch9::Mutex<u64> {
+    const NAME: &'static str = "ch9::Mutex";
+    fn new() -> Self {
+        ch9::Mutex::new(0)
+    }
+    fn increment(&self) {
+        *self.lock() += 1;
+    }
+}
+
+impl Lock for Mutex<u64> {
+    const NAME: &'static str = "std::sync::Mutex";
+    fn new() -> Self {
+        Mutex::new(0)
+    }
+    fn increment(&self) {
+        *self.lock().unwrap() += 1;
+    }
+}
+
+/// Every thread locks, increments a counter and unlocks, as often as it can.
+pub fn lock<L: Lock>(bench: &Bench, scenario: Scenario) -> Report {
+    let shared = L::new();
+    bench.run(L::NAME, scenario, |_| {
+        let own = L::new();
+        let shared = &shared;
+        move || {
+            let start = Instant::now();
+            match scenario {
+                Scenario::Uncontended => own.increment(),
+                Scenario::Contended => shared.increment(),
+            }
+            start.elapsed()
+        }
+    })
+}
+
+pub fn spin_lock(bench: &Bench, scenario: Scenario) -> Report {
+    lock::<SpinLock<u64>>(bench, scenario)
+}
+
+/// The thread counts [queue_locks] runs at.
+pub const QUEUE_LOCK_THREADS: [usize; 3] = [2, 8, 32];
+
+/// [McsLock] and [ClhLock] against [SpinLockFlag] (and [TicketLock], the other fair lock) at every one of [QUEUE_LOCK_THREADS], contended.
+/// Ignores [Bench::threads]. With more threads than cores, the fair locks are slower: the next thread in line is often not running.
+pub fn queue_locks(bench: &Bench) -> Vec<Report> {
+    let mut reports = Vec::new();
+    for threads in QUEUE_LOCK_THREADS {
+        let bench = bench.threads(threads);
+        reports.extend([
+            lock::<SpinLockFlag>(&bench, Scenario::Contended),
+            lock::<TicketLock<u64>>(&bench, Scenario::Contended),
+            lock::<McsLock<u64>>(&bench, Scenario::Contended),
+            lock::<ClhLock<u64>>(&bench, Scenario::Contended),
+        ]);
+    }
+    reports
+}
+
+/// Every thread sends a message, then receives one, through a [SimpleChannel].
+/// The latency is from sending the message to receiving it, which is a message of another thread in [Scenario::Contended].
+pub fn simple_channel(bench: &Bench, scenario: Scenario) -> Report {
+    let shared = SimpleChannel::new();
+    bench.run("SimpleChannel", scenario, |_| {
+        let own = SimpleChannel::new();
+        let shared = &shared;
+        move || {
+            let channel = match scenario {
+                Scenario::Uncontended => &own,
+                Scenario::Contended => shared,
+            };
+            channel.send(Instant::now()).unwrap();
+            channel.receive().unwrap().elapsed()
+        }
+    })
+}
+
+/// Sends one message through a new [OneshotChannel].
+/// - [Scenario::Uncontended]: the thread sends the message to itself.
+/// - [Scenario::Contended]: every thread asks the same server thread for the message, the latency is the whole round trip.
+///   The threads queue up at the server, so this measures the channel while the server is busy with the other threads.
+pub fn oneshot_channel(bench: &Bench, scenario: Scenario) -> Report {
+    let server = Server::new(scenario, |channel: Arc<OneshotChannel<Instant>>| {
+        channel.send(Instant::now())
+    });
+    bench.run("OneshotChannel", scenario, |_| {
+        let server = &server;
+        move || {
+            let start = Instant::now();
+            let channel = Arc::new(OneshotChannel::new());
+            match server {
+                None => channel.send(start),
+                Some(server) => server.request(channel.clone()),
+            }
+            block_on(channel.receive_async());
+            start.elapsed()
+        }
+    })
+}
+
+/// Sends one message through a new [ch5::channel], like [oneshot_channel].
+pub fn channel(bench: &Bench, scenario: Scenario) -> Report {
+    let server = Server::new(scenario, |sender: ch5::Sender<Instant>| {
+        sender.send(Instant::now())
+    });
+    bench.run("channel", scenario, |_| {
+        let server = &server;
+        move || {
+            let start = Instant::now();
+            let (sender, receiver) = ch5::channel();
+            match server {
+                None => sender.send(start),
+                Some(server) => server.request(sender),
+            }
+            receiver.recv().unwrap();
+            start.elapsed()
+        }
+    })
+}
+
+/// Every thread sends a message, then receives one, through a bounded [mpmc::channel].
+/// The latency is from sending the message to receiving it, which is a message of another thread in [Scenario::Contended].
+pub fn mpmc_channel(bench: &Bench, scenario: Scenario) -> Report {
+    // every thread only sends when it received as many messages as it sent, so this never fills up
+    let (shared_sender, shared_receiver) = mpmc::channel(bench.threads);
+    bench.run("mpmc::channel", scenario, |_| {
+        let (sender, receiver) = match scenario {
+            Scenario::Uncontended => mpmc::channel(1),
+            Scenario::Contended => (shared_sender.clone(), shared_receiver.clone()),
+        };
+        move || {
+            sender.send(Instant::now()).unwrap();
+            receiver.recv().unwrap().elapsed()
+        }
+    })
+}
+
+/// A thread that handles the requests of every benchmark thread, one at a time. Stops when the [Server] is dropped.
+struct Server<T> {
+    /// Always [Some] until the [Server] is dropped. Dropping it ends the server thread's loop.
+    requests: Option<std::sync::mpsc::Sender<T>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+impl<T: Send + 'static> Server<T> {
+    /// Only [Scenario::Contended] needs a server.
+    fn new(scenario: Scenario, handle: impl Fn(T) + Send + 'static) -> Option<Self> {
+        if scenario == Scenario::Uncontended {
+            return None;
+        }
+        let (requests, receiver) = std::sync::mpsc::channel();
+        let thread = std::thread::spawn(move || receiver.into_iter().for_each(handle));
+        Some(Self {
+            requests: Some(requests),
+            thread: Some(thread),
+        })
+    }
+
+    fn request(&self, request: T) {
+        self.requests.as_ref().unwrap().send(request).unwrap();
+    }
+}
+
+impl<T> Drop for Server<T> {
+    fn drop(&mut self) {
+        drop(self.requests.take());
+        if let Some(thread) = self.thread.take() {
+            // don't panic again if a benchmark thread is already panicking
+            if thread.join().is_err() && !std::thread::panicking() {
+                panic!("the server thread panicked");
+            }
+        }
+    }
+}
+
+#[test]
+fn bench_latencies() {
+    let mut latencies = Latencies::new();
+    for nanos in 1..=1000 {
+        latencies.record(Duration::from_nanos(nanos));
+    }
+    assert_eq!(latencies.count(), 1000);
+
+    // every bucket's upper bound is in the bucket, and the next value isn't
+    for nanos in [0, 3, 4, 9, 10, 1000, u64::MAX / 2] {
+        let bucket = Latencies::bucket(nanos);
+        assert_eq!(Latencies::bucket(Latencies::upper_bound(bucket)), bucket);
+        assert!(Latencies::bucket(Latencies::upper_bound(bucket) + 1) > bucket);
+    }
+
+    for (percentile, nanos) in [(50.0, 500), (99.0, 990)] {
+        let upper_bound = latencies.percentile(percentile).as_nanos() as f64;
+        assert!(upper_bound >= nanos as f64 && upper_bound <= nanos as f64 * 1.25);
+    }
+}
+
+#[test]
+fn bench_every_benchmark() {
+    let bench = Bench::new()
+        .threads(2)
+        .warmup(Duration::from_millis(5))
+        .duration(Duration::from_millis(20));
+
+    let mut reports = Vec::new();
+    for scenario in Scenario::ALL {
+        reports.push(simple_channel(&bench, scenario));
+        reports.push(spin_lock(&bench, scenario));
+        reports.push(lock::<ch9::Mutex<u64>>(&bench, scenario));
+        reports.push(lock::<Mutex<u64>>(&bench, scenario));
+        reports.push(lock::<SpinLockFlag>(&bench, scenario));
+        reports.push(lock::<TicketLock<u64>>(&bench, scenario));
+        reports.push(lock::<McsLock<u64>>(&bench, scenario));
+        reports.push(lock::<ClhLock<u64>>(&bench, scenario));
+        reports.push(oneshot_channel(&bench, scenario));
+        reports.push(channel(&bench, scenario));
+        reports.push(mpmc_channel(&bench, scenario));
+    }
+
+    let columns = Report::CSV_HEADER.split(',').count();
+    for report in reports {
+        println!("{report}");
+        assert_eq!(report.threads.len(), 2);
+        assert!(report.operations() > 0);
+        assert_eq!(report.latencies().count(), report.operations());
+        assert_eq!(report.to_csv().split(',').count(), columns);
+        assert!(report
+            .to_json()
+            .starts_with(&format!("{{\"name\":\"{}\"", report.name)));
+    }
+}
+
+#[test]
+fn bench_queue_locks() {
+    let bench = Bench::new()
+        .warmup(Duration::from_millis(5))
+        .duration(Duration::from_millis(20));
+
+    let reports = queue_locks(&bench);
+    assert_eq!(reports.len(), 4 * QUEUE_LOCK_THREADS.len());
+    let threads = QUEUE_LOCK_THREADS.iter().flat_map(|&threads| [threads; 4]);
+    for (report, threads) in reports.iter().zip(threads) {
+        println!("{report}");
+        assert_eq!(report.threads.len(), threads);
+        assert!(report.operations() > 0);
+    }
+}
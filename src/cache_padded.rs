@@ -0,0 +1,20 @@
+//! Keeping hot atomics apart, so threads writing to different values don't keep stealing one cache line from each other (false sharing).
+//! Used by the queue locks in [crate::ch4::queue_lock] and the ring buffer in [crate::ch5::spsc].
+
+use std::ops::{Deref, DerefMut};
+
+/// Aligns `T` to 128 bytes (two cache lines, adjacent lines are prefetched together) so it doesn't share a cache line with anything else.
+#[derive(Debug, Default)]
+#[repr(align(128))]
+pub struct CachePadded<T>(pub T);
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+impl<T> DerefMut for CachePadded<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
@@ -13,6 +13,8 @@
 
 use super::*;
 
+pub mod litmus;
+
 #[test]
 fn seqcst() {
     static IS_THREAD_A_ACCESSING_S: AtomicBool = AtomicBool::new(false);
@@ -27,7 +29,7 @@ fn seqcst() {
         // check if thread b is accessing S
         if !IS_THREAD_B_ACCESSING_S.load(SeqCst) {
             // SAFETY: the accessing S from thread b flag was not set
-            unsafe { S.push_str("\npushed from a\n") };
+            unsafe { (*ptr::addr_of_mut!(S)).push_str("\npushed from a\n") };
         }
     });
 
@@ -38,25 +40,24 @@ fn seqcst() {
         // check if thread a is accessing S
         if !IS_THREAD_A_ACCESSING_S.load(SeqCst) {
             // SAFETY: the accessing S from thread a flag was not set
-            unsafe { S.push_str("\npushed from b\n") };
+            unsafe { (*ptr::addr_of_mut!(S)).push_str("\npushed from b\n") };
         }
     });
 
     a.join().unwrap();
     b.join().unwrap();
 
-    println!("{}", unsafe { S.as_str() });
+    println!("{}", unsafe { (*ptr::addr_of!(S)).as_str() });
 }
 
 #[test]
 fn conditional_fence() {
     fn some_calculation(i: usize) -> u64 {
-        return (2 * i) as u64;
+        (2 * i) as u64
     }
 
     static mut DATA: [u64; 10] = [0; 10];
-    const ATOMIC_FALSE: AtomicBool = AtomicBool::new(false);
-    static READY: [AtomicBool; 10] = [ATOMIC_FALSE; 10];
+    static READY: [AtomicBool; 10] = [const { AtomicBool::new(false) }; 10];
 
     for thread_index in 0..10 {
         thread::spawn(move || {
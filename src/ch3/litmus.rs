@@ -0,0 +1,358 @@
+//! Litmus tests: tiny programs whose outcome shows which reorderings the hardware (and compiler) actually do.
+//! - [run] runs a [Shape] over and over with the chosen [Orderings], and counts how often every outcome shows up.
+//! - Some outcomes are forbidden by the memory model for some orderings, see [Shape::forbidden].
+//!   [Histogram::assert_allowed] panics if one of those was ever observed.
+//! - An allowed outcome might still never show up: x86 doesn't reorder much, and a single core doesn't reorder anything.
+//!
+//! Every iteration uses its own zeroed `Slot`. Threads go through a batch of slots together,
+//! so the iterations of different threads overlap without a barrier between every single iteration.
+
+use std::{collections::BTreeMap, fmt, sync::Barrier};
+
+use super::*;
+
+/// The classic litmus test shapes. `x` and `y` start at 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shape {
+    /// Store buffering. `x = 1; r0 = y` || `y = 1; r1 = x`.
+    /// `r0 == 0 && r1 == 0` means both loads were done before both stores.
+    StoreBuffering,
+    /// Message passing. `x = 1; y = 1` || `r0 = y; r1 = x`, only the flag `y` uses the [Orderings].
+    /// `r0 == 1 && r1 == 0` means the flag was seen but the message wasn't.
+    MessagePassing,
+    /// Load buffering. `r0 = x; y = 1` || `r1 = y; x = 1`.
+    /// `r0 == 1 && r1 == 1` means both loads read a store that comes after the other load.
+    LoadBuffering,
+    /// Independent reads of independent writes. `x = 1` || `y = 1` || `r0 = x; r1 = y` || `r2 = y; r3 = x`.
+    /// `r0 == 1 && r1 == 0 && r2 == 1 && r3 == 0` means the two readers disagree about which store happened first.
+    IndependentReadsOfIndependentWrites,
+    /// Two plus two writes. `x = 1; y = 2` || `y = 1; x = 2`, `r0` and `r1` are the final values of `x` and `y`.
+    /// `r0 == 1 && r1 == 1` means both threads' second store came before the other thread's first store.
+    TwoPlusTwoWrites,
+}
+
+impl Shape {
+    pub const ALL: [Self; 5] = [
+        Self::StoreBuffering,
+        Self::MessagePassing,
+        Self::LoadBuffering,
+        Self::IndependentReadsOfIndependentWrites,
+        Self::TwoPlusTwoWrites,
+    ];
+
+    /// The usual short name: SB, MP, LB, IRIW or 2+2W.
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::StoreBuffering => "SB",
+            Self::MessagePassing => "MP",
+            Self::LoadBuffering => "LB",
+            Self::IndependentReadsOfIndependentWrites => "IRIW",
+            Self::TwoPlusTwoWrites => "2+2W",
+        }
+    }
+
+    /// Looks a shape up by its [Shape::name], ignoring case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|shape| shape.name().eq_ignore_ascii_case(name))
+    }
+
+    pub const fn thread_count(self) -> usize {
+        match self {
+            Self::IndependentReadsOfIndependentWrites => 4,
+            _ => 2,
+        }
+    }
+
+    /// The number of registers in an outcome.
+    pub const fn register_count(self) -> usize {
+        match self {
+            Self::IndependentReadsOfIndependentWrites => 4,
+            _ => 2,
+        }
+    }
+
+    /// The outcome the memory model rules out with these orderings, if any.
+    /// Only outcomes forbidden by the memory model itself are listed, not the ones x86 happens to never produce.
+    pub const fn forbidden(self, orderings: Orderings) -> Option<&'static [usize]> {
+        use Orderings as O;
+        match (self, orderings) {
+            (Self::StoreBuffering, O::SeqCst) => Some(&[0, 0]),
+            (Self::MessagePassing, O::ReleaseAcquire | O::SeqCst) => Some(&[1, 0]),
+            (Self::LoadBuffering, O::ReleaseAcquire | O::SeqCst) => Some(&[1, 1]),
+            (Self::IndependentReadsOfIndependentWrites, O::SeqCst) => Some(&[1, 0, 1, 0]),
+            (Self::TwoPlusTwoWrites, O::SeqCst) => Some(&[1, 1]),
+            _ => None,
+        }
+    }
+
+    /// What `thread` does in a single iteration.
+    fn run_thread(self, thread: usize, orderings: Orderings, slot: &Slot) {
+        let (store, load) = (orderings.store(), orderings.load());
+        let [x, y] = &slot.locations;
+        let r = &slot.registers;
+        match (self, thread) {
+            (Self::StoreBuffering, 0) => {
+                x.store(1, store);
+                r[0].store(y.load(load), Relaxed);
+            }
+            (Self::StoreBuffering, _) => {
+                y.store(1, store);
+                r[1].store(x.load(load), Relaxed);
+            }
+            (Self::MessagePassing, 0) => {
+                x.store(1, Relaxed);
+                y.store(1, store);
+            }
+            (Self::MessagePassing, _) => {
+                r[0].store(y.load(load), Relaxed);
+                r[1].store(x.load(Relaxed), Relaxed);
+            }
+            (Self::LoadBuffering, 0) => {
+                r[0].store(x.load(load), Relaxed);
+                y.store(1, store);
+            }
+            (Self::LoadBuffering, _) => {
+                r[1].store(y.load(load), Relaxed);
+                x.store(1, store);
+            }
+            (Self::IndependentReadsOfIndependentWrites, 0) => x.store(1, store),
+            (Self::IndependentReadsOfIndependentWrites, 1) => y.store(1, store),
+            (Self::IndependentReadsOfIndependentWrites, 2) => {
+                r[0].store(x.load(load), Relaxed);
+                r[1].store(y.load(load), Relaxed);
+            }
+            (Self::IndependentReadsOfIndependentWrites, _) => {
+                r[2].store(y.load(load), Relaxed);
+                r[3].store(x.load(load), Relaxed);
+            }
+            (Self::TwoPlusTwoWrites, 0) => {
+                x.store(1, store);
+                y.store(2, store);
+            }
+            (Self::TwoPlusTwoWrites, _) => {
+                y.store(1, store);
+                x.store(2, store);
+            }
+        }
+    }
+
+    /// Reads the outcome of an iteration once every thread is done with it.
+    fn outcome(self, slot: &Slot) -> Vec<usize> {
+        match self {
+            // the final values are the outcome
+            Self::TwoPlusTwoWrites => slot.locations.iter().map(|l| l.load(Relaxed)).collect(),
+            _ => slot.registers[..self.register_count()]
+                .iter()
+                .map(|r| r.load(Relaxed))
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Display for Shape {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The memory orderings used by a litmus test.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orderings {
+    /// [Relaxed] stores and loads.
+    Relaxed,
+    /// [Release] stores and [Acquire] loads.
+    ReleaseAcquire,
+    /// [SeqCst] stores and loads.
+    SeqCst,
+}
+
+impl Orderings {
+    pub const ALL: [Self; 3] = [Self::Relaxed, Self::ReleaseAcquire, Self::SeqCst];
+
+    /// Looks orderings up by their [fmt::Display] name, ignoring case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|orderings| orderings.to_string().eq_ignore_ascii_case(name))
+    }
+
+    pub const fn store(self) -> Ordering {
+        match self {
+            Self::Relaxed => Relaxed,
+            Self::ReleaseAcquire => Release,
+            Self::SeqCst => SeqCst,
+        }
+    }
+
+    pub const fn load(self) -> Ordering {
+        match self {
+            Self::Relaxed => Relaxed,
+            Self::ReleaseAcquire => Acquire,
+            Self::SeqCst => SeqCst,
+        }
+    }
+}
+
+impl fmt::Display for Orderings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Relaxed => "relaxed",
+            Self::ReleaseAcquire => "release/acquire",
+            Self::SeqCst => "seqcst",
+        })
+    }
+}
+
+/// The memory of a single iteration.
+#[derive(Default)]
+struct Slot {
+    /// `x` and `y`
+    locations: [AtomicUsize; 2],
+    /// `r0` to `r3`. Only read after the iteration is done, so they don't take part in the test.
+    registers: [AtomicUsize; 4],
+}
+
+impl Slot {
+    fn reset(&mut self) {
+        for value in self.locations.iter_mut().chain(&mut self.registers) {
+            *value.get_mut() = 0;
+        }
+    }
+}
+
+/// How many iterations the threads go through between two barriers.
+const BATCH_SIZE: usize = 1024;
+
+/// Runs `shape` `iterations` times and counts the outcomes.
+pub fn run(shape: Shape, orderings: Orderings, iterations: usize) -> Histogram {
+    let mut slots: Vec<Slot> = (0..BATCH_SIZE.min(iterations))
+        .map(|_| Slot::default())
+        .collect();
+    let mut counts = BTreeMap::new();
+
+    let mut remaining = iterations;
+    while remaining > 0 {
+        let batch = &mut slots[..remaining.min(BATCH_SIZE)];
+        batch.iter_mut().for_each(Slot::reset);
+        remaining -= batch.len();
+
+        let batch = &*batch;
+        // so the threads start at the same time, instead of one after the other as they are spawned
+        let start = Barrier::new(shape.thread_count());
+        thread::scope(|s| {
+            for thread in 0..shape.thread_count() {
+                let start = &start;
+                s.spawn(move || {
+                    start.wait();
+                    for slot in batch {
+                        shape.run_thread(thread, orderings, slot);
+                    }
+                });
+            }
+        });
+
+        for slot in batch {
+            *counts.entry(shape.outcome(slot)).or_insert(0) += 1;
+        }
+    }
+
+    Histogram {
+        shape,
+        orderings,
+        counts,
+    }
+}
+
+/// How often every outcome of a litmus test was observed.
+#[derive(Debug, Clone)]
+pub struct Histogram {
+    pub shape: Shape,
+    pub orderings: Orderings,
+    /// The registers `r0`, `r1`, ... of an outcome, and how many iterations ended with them.
+    pub counts: BTreeMap<Vec<usize>, u64>,
+}
+
+impl Histogram {
+    pub fn iterations(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    /// How many iterations ended with the outcome forbidden by [Shape::forbidden].
+    pub fn forbidden_count(&self) -> u64 {
+        match self.shape.forbidden(self.orderings) {
+            Some(forbidden) => self.counts.get(forbidden).copied().unwrap_or(0),
+            None => 0,
+        }
+    }
+
+    /// # Panics
+    /// - When the outcome forbidden by [Shape::forbidden] was observed
+    pub fn assert_allowed(&self) {
+        assert_eq!(
+            self.forbidden_count(),
+            0,
+            "a forbidden outcome was observed\n{self}"
+        );
+    }
+}
+
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} with {} orderings, {} iterations",
+            self.shape,
+            self.orderings,
+            self.iterations()
+        )?;
+        let forbidden = self.shape.forbidden(self.orderings);
+        for (outcome, count) in &self.counts {
+            for (i, value) in outcome.iter().enumerate() {
+                write!(f, "r{i}={value} ")?;
+            }
+            write!(f, "{count:>10}")?;
+            if forbidden == Some(outcome.as_slice()) {
+                write!(f, " forbidden")?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn litmus_forbidden_outcomes_never_happen() {
+    for shape in Shape::ALL {
+        for orderings in Orderings::ALL {
+            let histogram = run(shape, orderings, 20_000);
+            println!("{histogram}");
+            assert_eq!(histogram.iterations(), 20_000);
+            histogram.assert_allowed();
+        }
+    }
+}
+
+#[test]
+fn litmus_outcomes() {
+    // a thread that runs all of its iterations before the other thread starts is always possible
+    let histogram = run(Shape::StoreBuffering, Orderings::SeqCst, 1000);
+    for outcome in histogram.counts.keys() {
+        assert!([[0, 1], [1, 0], [1, 1]]
+            .iter()
+            .any(|o| o == outcome.as_slice()));
+    }
+
+    let histogram = run(Shape::TwoPlusTwoWrites, Orderings::SeqCst, 1000);
+    for outcome in histogram.counts.keys() {
+        assert!(outcome.iter().all(|&value| value == 1 || value == 2));
+    }
+
+    assert_eq!(
+        Shape::from_name("iriw"),
+        Some(Shape::IndependentReadsOfIndependentWrites)
+    );
+    assert_eq!(Shape::from_name("2+2w"), Some(Shape::TwoPlusTwoWrites));
+    assert_eq!(Shape::from_name("nope"), None);
+}
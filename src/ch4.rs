@@ -18,38 +18,177 @@
 //!   to a locked lock. Such a type usually behaves similarly to a reference, thanks to the Deref traits,
 //!   and implements automatic unlocking through the Drop trait.
 
+// this chapter spells out every return
+#![allow(clippy::needless_return)]
+
 use super::*;
+use crate::atomic_wait::{wait, wait_timeout, wake_one};
+
+/// How a thread waits between two failed attempts to lock a spin lock.
+/// The spin locks in this module use [Backoff::Spin] unless they are created with a `with_backoff` constructor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backoff {
+    /// One [std::hint::spin_loop] per failed attempt. Lowest latency, but a waiting thread keeps its core busy forever.
+    Spin,
+    /// Doubles the number of [std::hint::spin_loop]s after every failed attempt, up to `2^SPIN_LIMIT`.
+    /// Less traffic on the lock's cache line, still never gives up the core.
+    ExponentialSpin,
+    /// [Backoff::ExponentialSpin] until the limit is reached, then [thread::yield_now] so other threads can run.
+    SpinThenYield,
+    /// [Backoff::ExponentialSpin] until the limit is reached, then sleep (see [crate::atomic_wait::wait]) until the lock is unlocked.
+    /// Only [SpinLockFlag] (and the locks built on it) can be woken by an unlock. The other locks [thread::yield_now] instead.
+    SpinThenPark,
+    /// [Backoff::ExponentialSpin] until the limit is reached, then [thread::sleep].
+    /// The sleep doubles each time, up to `2^SLEEP_LIMIT` microseconds, so an unlock can go unnoticed for that long.
+    SpinThenSleep,
+}
+impl Backoff {
+    /// `2^SPIN_LIMIT` is the most [std::hint::spin_loop]s done between two attempts.
+    const SPIN_LIMIT: u32 = 6;
+    /// `2^SLEEP_LIMIT` is the longest time in microseconds [Backoff::SpinThenSleep] sleeps between two attempts.
+    const SLEEP_LIMIT: u32 = 10;
+
+    /// Starts waiting for a single lock acquisition.
+    pub const fn start(self) -> Snooze {
+        return Snooze {
+            backoff: self,
+            step: 0,
+        };
+    }
+}
+
+/// The state of a single lock acquisition. Call [Snooze::snooze] after every failed attempt.
+pub struct Snooze {
+    backoff: Backoff,
+    step: u32,
+}
+impl Snooze {
+    pub fn snooze(&mut self) {
+        let is_spinning = self.step <= Backoff::SPIN_LIMIT;
+        match self.backoff {
+            Backoff::Spin => hint::spin_loop(),
+            Backoff::ExponentialSpin => self.spin(),
+            Backoff::SpinThenYield if is_spinning => self.spin(),
+            Backoff::SpinThenYield => thread::yield_now(),
+            Backoff::SpinThenPark if is_spinning => self.spin(),
+            // nothing wakes a lock that calls snooze, see Backoff::SpinThenPark
+            Backoff::SpinThenPark => thread::yield_now(),
+            Backoff::SpinThenSleep if is_spinning => self.spin(),
+            Backoff::SpinThenSleep => {
+                let exponent = (self.step - Backoff::SPIN_LIMIT).min(Backoff::SLEEP_LIMIT);
+                thread::sleep(Duration::from_micros(1 << exponent));
+            }
+        }
+        self.step = self.step.saturating_add(1);
+    }
+    /// Whether the lock should go to sleep instead of calling [Snooze::snooze] again.
+    /// Only for locks that wake a sleeping thread when they are unlocked, see [Backoff::SpinThenPark].
+    pub fn should_park(&self) -> bool {
+        return self.backoff == Backoff::SpinThenPark && self.step > Backoff::SPIN_LIMIT;
+    }
+    fn spin(&self) {
+        for _ in 0..1 << self.step.min(Backoff::SPIN_LIMIT) {
+            // tell the processor that we are waiting using a loop.
+            // the processor doesn't have to listen
+            hint::spin_loop();
+        }
+    }
+}
 
-/// This struct is a small wrapper around [AtomicBool] representing whether some arbitrary data is accessible (**unlocked**).
+/// This struct is a small wrapper around an [AtomicU32] representing whether some arbitrary data is accessible (**unlocked**).
+/// Besides unlocked and locked, it tracks whether a thread sleeps waiting for it (only with [Backoff::SpinThenPark]),
+/// so [SpinLockFlag::unlock] only wakes a thread when there is one.
 /// - use [SpinLockFlag::lock] to signal any other threads that some data is locked and should not be accessed.
 /// - use [SpinLockFlag::unlock] to signal any other threads that some data is unlocked and another thread can lock.
 /// ## Safety
 /// The caller needs to make sure that any static mut data is only accessed while the [SpinLockFlag] instance is locked
 pub struct SpinLockFlag {
-    is_locked: AtomicBool,
+    /// [SpinLockFlag::UNLOCKED], [SpinLockFlag::LOCKED] or [SpinLockFlag::CONTENDED]
+    state: AtomicU32,
+    backoff: Backoff,
+}
+impl Default for SpinLockFlag {
+    fn default() -> Self {
+        return Self::new();
+    }
 }
 impl SpinLockFlag {
+    const UNLOCKED: u32 = 0;
+    const LOCKED: u32 = 1;
+    /// Locked, and other threads (maybe) sleep waiting for the unlock.
+    const CONTENDED: u32 = 2;
+
     pub const fn new() -> Self {
+        return Self::with_backoff(Backoff::Spin);
+    }
+    pub const fn with_backoff(backoff: Backoff) -> Self {
         return Self {
-            is_locked: AtomicBool::new(false),
+            state: AtomicU32::new(Self::UNLOCKED),
+            backoff,
         };
     }
     pub fn lock(&self) {
-        // TODO: after a set number of loops we should put this thread to sleep before spinning again
-        while self
-            .is_locked
-            // if is_locked == false, then acquire-load the old_value to be returned; afterwards relaxed-store true value to is_locked. return old_value as an Ok
+        self.lock_with_deadline(None);
+    }
+    /// Returns `false` if the lock is already locked, without waiting.
+    pub fn try_lock(&self) -> bool {
+        return self
+            .state
+            // if state == UNLOCKED, then acquire-load the old_value to be returned; afterwards relaxed-store LOCKED to state. return old_value as an Ok
             // else relaxed-load the old_value and return it as an Err
-            .compare_exchange_weak(false, true, Acquire, Relaxed)
-            .is_err()
-        {
-            // tell the OS that we are waiting using a loop.
-            // OS doesn't have to listen
-            std::hint::spin_loop();
+            // not compare_exchange_weak: a spurious failure would make try_lock fail while the lock is unlocked
+            .compare_exchange(Self::UNLOCKED, Self::LOCKED, Acquire, Relaxed)
+            .is_ok();
+    }
+    /// Returns `false` if the lock could not be locked within `timeout`.
+    pub fn lock_timeout(&self, timeout: Duration) -> bool {
+        return self.lock_with_deadline(Instant::now().checked_add(timeout));
+    }
+    /// Returns `false` if the lock could not be locked before `deadline`.
+    pub fn lock_until(&self, deadline: Instant) -> bool {
+        return self.lock_with_deadline(Some(deadline));
+    }
+    /// The acquisition loop behind the `lock*` methods of [SpinLockFlag], [UnsafeSpinLock] and [SpinLock].
+    /// `deadline == None` means wait forever.
+    fn lock_with_deadline(&self, deadline: Option<Instant>) -> bool {
+        let mut snooze = self.backoff.start();
+        while !self.try_lock() {
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return false;
+            }
+            if snooze.should_park() {
+                return self.park_with_deadline(deadline);
+            }
+            // wait a bit before trying again. the longer we have been waiting, the longer we wait
+            snooze.snooze();
         }
+        return true;
+    }
+    /// Sleeps until the lock is unlocked, and locks it. Like the futex based [crate::ch9::Mutex].
+    #[cold]
+    fn park_with_deadline(&self, deadline: Option<Instant>) -> bool {
+        // mark the lock as contended so the unlock wakes us.
+        // when swap returns UNLOCKED we got the lock, but we don't know if there are other sleepers, so it stays CONTENDED
+        while self.state.swap(Self::CONTENDED, Acquire) != Self::UNLOCKED {
+            match deadline {
+                None => wait(&self.state, Self::CONTENDED),
+                Some(deadline) => {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if remaining.is_zero() {
+                        // the lock stays CONTENDED, so its unlock might wake nobody once, which is harmless
+                        return false;
+                    }
+                    wait_timeout(&self.state, Self::CONTENDED, remaining);
+                }
+            }
+        }
+        return true;
     }
     pub fn unlock(&self) {
-        self.is_locked.store(false, Release);
+        // only wake a thread if one marked itself as sleeping
+        if self.state.swap(Self::UNLOCKED, Release) == Self::CONTENDED {
+            wake_one(&self.state);
+        }
     }
 }
 
@@ -62,18 +201,44 @@ pub struct UnsafeSpinLock<T> {
 // Note that we don’t need to require that T is Sync, because our SpinLock<T> will only allow one thread at a time to access the T it protects.
 // Only if we were to give multiple threads access at once, like a reader-writer lock does for readers, would we (additionally) need to require T: Sync.
 unsafe impl<T: Send> Sync for UnsafeSpinLock<T> {}
+// handing out a `&mut T` from a `&self` is the point of this lock: the caller promises to call the unsafe unlock only after it is gone
+#[allow(clippy::mut_from_ref)]
 impl<T> UnsafeSpinLock<T> {
     pub const fn new(value: T) -> Self {
+        return Self::with_backoff(value, Backoff::Spin);
+    }
+    pub const fn with_backoff(value: T, backoff: Backoff) -> Self {
         return Self {
-            protector: SpinLockFlag::new(),
+            protector: SpinLockFlag::with_backoff(backoff),
             value: UnsafeCell::new(value),
         };
     }
-    pub fn lock<'a>(&'a self) -> &'a mut T {
+    pub fn lock(&self) -> &mut T {
         self.protector.lock();
         let pointer = self.value.get();
         return unsafe { &mut *pointer };
     }
+    /// Returns [None] if the lock is already locked, without waiting.
+    pub fn try_lock(&self) -> Option<&mut T> {
+        return self
+            .protector
+            .try_lock()
+            .then(|| unsafe { &mut *self.value.get() });
+    }
+    /// Returns [None] if the lock could not be locked within `timeout`.
+    pub fn lock_timeout(&self, timeout: Duration) -> Option<&mut T> {
+        return self
+            .protector
+            .lock_timeout(timeout)
+            .then(|| unsafe { &mut *self.value.get() });
+    }
+    /// Returns [None] if the lock could not be locked before `deadline`.
+    pub fn lock_until(&self, deadline: Instant) -> Option<&mut T> {
+        return self
+            .protector
+            .lock_until(deadline)
+            .then(|| unsafe { &mut *self.value.get() });
+    }
     /// # Safety
     /// The mutable reference from [UnsafeSpinLock::lock] must be gone!!
     /// This includes any references to fields of `T`
@@ -82,27 +247,78 @@ impl<T> UnsafeSpinLock<T> {
     }
 }
 
-mod safe_spin_lock {
-    use std::ops::{Deref, DerefMut};
+mod poison;
 
+pub mod safe_spin_lock {
     use super::*;
 
     /// Identical to [UnsafeSpinLock] except that [SpinLock::lock] returns a [Guard<'a, T>] not a `&mut T`
+    ///
+    /// Like [std::sync::Mutex], a [SpinLock] is **poisoned** when a thread panics while holding its [Guard].
+    /// Every later [SpinLock::lock] returns an `Err` so the next locker knows `T` might be half-mutated.
+    /// The [Guard] can still be taken out of the [PoisonError] with [PoisonError::into_inner].
     pub struct SpinLock<T> {
         protector: SpinLockFlag,
+        poison: poison::Flag,
         value: UnsafeCell<T>,
     }
     unsafe impl<T: Send> Sync for SpinLock<T> {}
     impl<T> SpinLock<T> {
         pub const fn new(value: T) -> Self {
+            return Self::with_backoff(value, Backoff::Spin);
+        }
+        pub const fn with_backoff(value: T, backoff: Backoff) -> Self {
             return Self {
-                protector: SpinLockFlag::new(),
+                protector: SpinLockFlag::with_backoff(backoff),
+                poison: poison::Flag::new(),
                 value: UnsafeCell::new(value),
             };
         }
-        pub fn lock<'a>(&'a self) -> Guard<'a, T> {
+        /// # Errors
+        /// - When another thread panicked while holding a [Guard]. The [PoisonError] still contains the [Guard]
+        pub fn lock(&self) -> LockResult<Guard<'_, T>> {
             self.protector.lock();
-            return Guard { inner: self };
+            return self.guard();
+        }
+        /// # Errors
+        /// - [TryLockError::WouldBlock] when the [SpinLock] is already locked. This method doesn't wait
+        /// - [TryLockError::Poisoned] like [SpinLock::lock]
+        pub fn try_lock(&self) -> TryLockResult<Guard<'_, T>> {
+            return self.try_guard(self.protector.try_lock());
+        }
+        /// # Errors
+        /// - [TryLockError::WouldBlock] when the [SpinLock] could not be locked within `timeout`
+        /// - [TryLockError::Poisoned] like [SpinLock::lock]
+        pub fn lock_timeout(&self, timeout: Duration) -> TryLockResult<Guard<'_, T>> {
+            return self.try_guard(self.protector.lock_timeout(timeout));
+        }
+        /// # Errors
+        /// - [TryLockError::WouldBlock] when the [SpinLock] could not be locked before `deadline`
+        /// - [TryLockError::Poisoned] like [SpinLock::lock]
+        pub fn lock_until(&self, deadline: Instant) -> TryLockResult<Guard<'_, T>> {
+            return self.try_guard(self.protector.lock_until(deadline));
+        }
+        fn try_guard(&self, is_locked: bool) -> TryLockResult<Guard<'_, T>> {
+            if !is_locked {
+                return Err(TryLockError::WouldBlock);
+            }
+            return Ok(self.guard()?);
+        }
+        /// Wraps a [Guard] for an already locked [SpinLock] into a [LockResult] depending on [SpinLock::is_poisoned].
+        fn guard(&self) -> LockResult<Guard<'_, T>> {
+            return self.poison.guard(|panic| Guard { inner: self, panic });
+        }
+        pub fn is_poisoned(&self) -> bool {
+            return self.poison.get();
+        }
+        /// Marks the [SpinLock] as not poisoned. Call this after restoring `T` to a valid state.
+        pub fn clear_poison(&self) {
+            self.poison.clear();
+        }
+        /// # Errors
+        /// - When the [SpinLock] is poisoned. The [PoisonError] still contains the `T`
+        pub fn into_inner(self) -> LockResult<T> {
+            return self.poison.result(self.value.into_inner());
         }
     }
 
@@ -114,36 +330,148 @@ mod safe_spin_lock {
     ///     - guard's field is private
     ///     - [Guard] is defined in a unique module
     ///
-    /// [Guard] is [Deref] and [DerefMut] as `T
+    /// [Guard] is [Deref](std::ops::Deref) and [DerefMut](std::ops::DerefMut) as `T
+    ///
+    /// When a [Guard] is dropped because of a panic the [SpinLock] becomes poisoned.
     pub struct Guard<'a, T> {
         inner: &'a SpinLock<T>,
+        panic: poison::PanicCheck,
     }
     unsafe impl<T: Sync> Sync for Guard<'_, T> {}
     impl<T> Drop for Guard<'_, T> {
         fn drop(&mut self) {
-            println!("Guard::drop called");
+            self.inner.poison.done(&self.panic);
             self.inner.protector.unlock();
         }
     }
-    impl<T> Deref for Guard<'_, T> {
-        type Target = T;
-        fn deref(&self) -> &Self::Target {
-            // SAFETY: Guard's invariant is that it only exists
-            // when there is exclusive access to the inner T.
-            // self.guarded.protector.is_locked == true
-            return unsafe { &*self.inner.value.get() };
+    impl<'a, T> crate::ch9::CondvarGuard<'a> for Guard<'a, T> {
+        type Lock = SpinLock<T>;
+        fn unlock(self) -> &'a SpinLock<T> {
+            return self.inner;
+        }
+        fn relock(lock: &'a SpinLock<T>) -> LockResult<Self> {
+            return lock.lock();
+        }
+    }
+    poison::guard_deref!(Guard);
+}
+pub use safe_spin_lock::*;
+
+pub mod ticket_lock {
+    use super::*;
+
+    /// A fair spin lock: threads get the lock in the order they called [TicketLock::lock], first come, first served.
+    /// [TicketLock::lock] draws a ticket from `next_ticket` and waits until `now_serving` gets to it.
+    /// Unlocking serves the next ticket.
+    ///
+    /// [SpinLock] lets whichever waiter happens to try first take the lock, so a thread can be starved indefinitely.
+    /// Here a waiter is only passed by the threads that drew a ticket before it.
+    ///
+    /// Poisoned like [SpinLock] when a thread panics while holding its [Guard].
+    pub struct TicketLock<T> {
+        /// The ticket the next [TicketLock::lock] draws.
+        next_ticket: AtomicU32,
+        /// The ticket of the thread that holds the lock, or gets it next.
+        now_serving: AtomicU32,
+        backoff: Backoff,
+        poison: poison::Flag,
+        value: UnsafeCell<T>,
+    }
+    unsafe impl<T: Send> Sync for TicketLock<T> {}
+    impl<T> TicketLock<T> {
+        /// Uses [Backoff::SpinThenYield]: a sleeping thread would keep every thread behind it waiting until it wakes up, even when its ticket is served.
+        pub const fn new(value: T) -> Self {
+            return Self::with_backoff(value, Backoff::SpinThenYield);
+        }
+        pub const fn with_backoff(value: T, backoff: Backoff) -> Self {
+            return Self {
+                next_ticket: AtomicU32::new(0),
+                now_serving: AtomicU32::new(0),
+                backoff,
+                poison: poison::Flag::new(),
+                value: UnsafeCell::new(value),
+            };
+        }
+        /// # Errors
+        /// - When another thread panicked while holding a [Guard]. The [PoisonError] still contains the [Guard]
+        pub fn lock(&self) -> LockResult<Guard<'_, T>> {
+            // the tickets wrap around, which is fine as long as less than u32::MAX threads wait at the same time
+            let ticket = self.next_ticket.fetch_add(1, Relaxed);
+            let mut snooze = self.backoff.start();
+            // Acquire: happens-after the unlock that served our ticket
+            while self.now_serving.load(Acquire) != ticket {
+                snooze.snooze();
+            }
+            return self.guard();
+        }
+        /// Only draws a ticket if it would be served right away, so it never waits.
+        /// # Errors
+        /// - [TryLockError::WouldBlock] when the [TicketLock] is locked, or other threads are waiting for it
+        /// - [TryLockError::Poisoned] like [TicketLock::lock]
+        pub fn try_lock(&self) -> TryLockResult<Guard<'_, T>> {
+            let serving = self.now_serving.load(Acquire);
+            if self
+                .next_ticket
+                .compare_exchange(serving, serving.wrapping_add(1), Relaxed, Relaxed)
+                .is_err()
+            {
+                return Err(TryLockError::WouldBlock);
+            }
+            return Ok(self.guard()?);
+        }
+        /// The number of tickets that were drawn but not served yet: the thread holding the lock and every thread waiting for it.
+        /// Only a snapshot, other threads might be locking and unlocking at the same time.
+        pub fn queue_len(&self) -> u32 {
+            let serving = self.now_serving.load(Relaxed);
+            return self.next_ticket.load(Relaxed).wrapping_sub(serving);
+        }
+        /// Wraps a [Guard] for an already locked [TicketLock] into a [LockResult] depending on [TicketLock::is_poisoned].
+        fn guard(&self) -> LockResult<Guard<'_, T>> {
+            return self.poison.guard(|panic| Guard { inner: self, panic });
+        }
+        pub fn is_poisoned(&self) -> bool {
+            return self.poison.get();
+        }
+        /// Marks the [TicketLock] as not poisoned. Call this after restoring `T` to a valid state.
+        pub fn clear_poison(&self) {
+            self.poison.clear();
+        }
+        /// # Errors
+        /// - When the [TicketLock] is poisoned. The [PoisonError] still contains the `T`
+        pub fn into_inner(self) -> LockResult<T> {
+            return self.poison.result(self.value.into_inner());
+        }
+    }
+
+    /// Exclusive access to the `T` of a [TicketLock], like [crate::ch4::Guard] for a [SpinLock].
+    /// Dropping it serves the next ticket.
+    pub struct Guard<'a, T> {
+        inner: &'a TicketLock<T>,
+        panic: poison::PanicCheck,
+    }
+    unsafe impl<T: Sync> Sync for Guard<'_, T> {}
+    impl<T> Drop for Guard<'_, T> {
+        fn drop(&mut self) {
+            self.inner.poison.done(&self.panic);
+            // only the thread holding the lock changes now_serving, so this can't skip a ticket
+            self.inner.now_serving.fetch_add(1, Release);
         }
     }
-    impl<T> DerefMut for Guard<'_, T> {
-        fn deref_mut(&mut self) -> &mut Self::Target {
-            // SAFETY: Guard's invariant is that it only exists
-            // when there is exclusive access to the inner T.
-            // self.guarded.protector.is_locked == true
-            return unsafe { &mut *self.inner.value.get() };
+    impl<'a, T> crate::ch9::CondvarGuard<'a> for Guard<'a, T> {
+        type Lock = TicketLock<T>;
+        fn unlock(self) -> &'a TicketLock<T> {
+            return self.inner;
+        }
+        fn relock(lock: &'a TicketLock<T>) -> LockResult<Self> {
+            return lock.lock();
         }
     }
+    poison::guard_deref!(Guard);
 }
-use safe_spin_lock::*;
+pub use ticket_lock::TicketLock;
+
+pub mod queue_lock;
+pub use queue_lock::{ClhLock, McsLock};
 
 #[test]
 fn safe_spin_lock() {
@@ -152,39 +480,289 @@ fn safe_spin_lock() {
     thread::scope(|s| {
         for i in 0..10 {
             s.spawn(move || {
-                DATA.lock().push(i);
+                DATA.lock().unwrap().push(i);
                 thread::sleep(Duration::from_secs(1));
             });
         }
         for i in 10..20 {
             s.spawn(move || {
-                DATA.lock().push(i);
+                DATA.lock().unwrap().push(i);
             });
         }
     });
 
-    for i in DATA.lock().iter() {
+    for i in DATA.lock().unwrap().iter() {
         print!("{}, ", i);
     }
 }
 
 #[test]
 fn poison_spin_lock() {
-    // TODO: figure out how to poison SpinLock
     static DATA: SpinLock<Vec<usize>> = SpinLock::new(Vec::new());
 
-    thread::spawn(move || {
-        let data = DATA.lock();
-        panic!("uh oh the guard is never dropped!"); // panic calls destructors
+    let panicking_thread = thread::spawn(move || {
+        let mut data = DATA.lock().unwrap();
+        data.push(usize::MAX);
+        panic!("uh oh the data is only half mutated!"); // panic calls destructors
+    });
+    assert!(panicking_thread.join().is_err());
+
+    assert!(DATA.is_poisoned());
+
+    // every thread sees the poison, but can still get to the data
+    thread::scope(|s| {
+        for i in 0..10 {
+            s.spawn(move || {
+                let mut data = DATA
+                    .lock()
+                    .expect_err("the lock should be poisoned")
+                    .into_inner();
+                data.push(i);
+            });
+        }
+    });
+
+    // restore the data and clear the poison
+    let mut data = DATA.lock().unwrap_err().into_inner();
+    data.retain(|&i| i != usize::MAX);
+    DATA.clear_poison();
+    drop(data);
+
+    assert!(!DATA.is_poisoned());
+    let mut data = DATA.lock().unwrap().clone();
+    data.sort();
+    assert_eq!(data, (0..10).collect::<Vec<_>>());
+}
+
+#[test]
+fn poison_spin_lock_into_inner() {
+    let lock = SpinLock::new(0);
+
+    thread::scope(|s| {
+        let result = s
+            .spawn(|| {
+                let _guard = lock.lock().unwrap();
+                panic!("poison the lock");
+            })
+            .join();
+        assert!(result.is_err());
+    });
+
+    assert_eq!(lock.into_inner().unwrap_err().into_inner(), 0);
+}
+
+#[test]
+fn oversubscribed_spin_lock() {
+    let threads_per_core = 8;
+    let thread_count = thread::available_parallelism().map_or(4, |n| n.get()) * threads_per_core;
+    let increments_per_thread = 200;
+
+    for backoff in [
+        Backoff::Spin,
+        Backoff::ExponentialSpin,
+        Backoff::SpinThenYield,
+        Backoff::SpinThenPark,
+        Backoff::SpinThenSleep,
+    ] {
+        let counter = SpinLock::with_backoff(0, backoff);
+
+        thread::scope(|s| {
+            for _ in 0..thread_count {
+                s.spawn(|| {
+                    for _ in 0..increments_per_thread {
+                        *counter.lock().unwrap() += 1;
+                    }
+                });
+            }
+        });
+
+        assert_eq!(
+            counter.into_inner().unwrap(),
+            thread_count * increments_per_thread,
+            "{backoff:?}"
+        );
+    }
+}
+
+#[test]
+fn try_lock_and_lock_timeout() {
+    let flag = SpinLockFlag::new();
+    assert!(flag.try_lock());
+    assert!(!flag.try_lock());
+    assert!(!flag.lock_timeout(Duration::from_millis(10)));
+    flag.unlock();
+    assert!(flag.lock_until(Instant::now()));
+    flag.unlock();
+
+    let unsafe_lock = UnsafeSpinLock::new(0);
+    *unsafe_lock.try_lock().unwrap() += 1;
+    assert!(unsafe_lock.try_lock().is_none());
+    assert!(unsafe_lock
+        .lock_timeout(Duration::from_millis(10))
+        .is_none());
+    // SAFETY: the reference from try_lock is gone
+    unsafe { unsafe_lock.unlock() };
+    assert_eq!(*unsafe_lock.lock_timeout(Duration::ZERO).unwrap(), 1);
+    unsafe { unsafe_lock.unlock() };
+
+    let lock = SpinLock::new(0);
+    thread::scope(|s| {
+        let guard = lock.lock().unwrap();
+
+        // a watchdog thread gives up on the wedged lock
+        s.spawn(|| {
+            let start = Instant::now();
+            let result = lock.lock_timeout(Duration::from_millis(50));
+            assert!(matches!(result, Err(TryLockError::WouldBlock)));
+            assert!(start.elapsed() >= Duration::from_millis(50));
+            assert!(matches!(lock.try_lock(), Err(TryLockError::WouldBlock)));
+        })
+        .join()
+        .unwrap();
+
+        drop(guard);
+
+        // and succeeds once the lock is released
+        s.spawn(|| {
+            *lock
+                .lock_until(Instant::now() + Duration::from_secs(1))
+                .unwrap() += 1;
+        });
     });
+    assert_eq!(*lock.try_lock().unwrap(), 1);
+}
+
+#[test]
+fn spin_then_park_is_woken_by_unlock() {
+    let flag = SpinLockFlag::with_backoff(Backoff::SpinThenPark);
+    flag.lock();
+    // long enough to stop spinning and go to sleep, then give up
+    assert!(!flag.lock_timeout(Duration::from_millis(10)));
+    assert_eq!(flag.state.load(Relaxed), SpinLockFlag::CONTENDED);
 
-    for i in 0..10 {
-        thread::spawn(move || {
-            DATA.lock().push(i);
+    thread::scope(|s| {
+        let waiter = s.spawn(|| {
+            flag.lock();
+            flag.unlock();
         });
+        thread::sleep(Duration::from_millis(50));
+        flag.unlock();
+        // hangs if the unlock doesn't wake the sleeping waiter
+        waiter.join().unwrap();
+    });
+    assert_eq!(flag.state.load(Relaxed), SpinLockFlag::UNLOCKED);
+}
+
+#[test]
+fn ticket_lock_is_first_come_first_served() {
+    let lock = TicketLock::new(Vec::new());
+    let thread_count = 8;
+
+    thread::scope(|s| {
+        let guard = lock.lock().unwrap();
+        for i in 0..thread_count {
+            let lock = &lock;
+            s.spawn(move || lock.lock().unwrap().push(i));
+            // wait until the thread drew its ticket, so the tickets are in spawn order
+            while lock.queue_len() != i + 2 {
+                thread::yield_now();
+            }
+        }
+        assert!(matches!(lock.try_lock(), Err(TryLockError::WouldBlock)));
+        drop(guard);
+    });
+
+    assert_eq!(lock.queue_len(), 0);
+    assert_eq!(
+        lock.into_inner().unwrap(),
+        (0..thread_count).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn ticket_lock_fairness() {
+    let thread_count = 4;
+    let locks_per_thread = 1000;
+    // the index of an entry is the acquisition's sequence number, which is also its ticket
+    let lock = TicketLock::new(Vec::new());
+
+    thread::scope(|s| {
+        for thread in 0..thread_count {
+            let lock = &lock;
+            s.spawn(move || {
+                for _ in 0..locks_per_thread {
+                    let mut acquisitions = lock.lock().unwrap();
+                    let queue_len = lock.queue_len() as usize;
+                    acquisitions.push((thread, queue_len));
+                }
+            });
+        }
+    });
+
+    let acquisitions = lock.into_inner().unwrap();
+    assert_eq!(acquisitions.len(), thread_count * locks_per_thread);
+    for (sequence, &(thread, queue_len)) in acquisitions.iter().enumerate() {
+        // every thread holds at most one ticket
+        assert!(
+            queue_len <= thread_count,
+            "{queue_len} tickets at {sequence}"
+        );
+        // the threads that drew the tickets after ours are served next, in order, each one once.
+        // so no thread waits for more than thread_count - 1 other acquisitions after drawing its ticket
+        let mut served_next: Vec<_> = acquisitions[sequence + 1..sequence + queue_len]
+            .iter()
+            .map(|&(thread, _)| thread)
+            .collect();
+        assert!(
+            !served_next.contains(&thread),
+            "{thread} cut in line at {sequence}"
+        );
+        served_next.sort();
+        served_next.dedup();
+        assert_eq!(
+            served_next.len(),
+            queue_len - 1,
+            "a thread cut in line at {sequence}"
+        );
     }
+}
+
+#[cfg(feature = "model")]
+#[test]
+fn model_spin_lock() {
+    use crate::ch6::Arc;
 
-    thread::sleep(Duration::from_secs(1));
+    let executions = model::Builder::new().check(|| {
+        let lock = Arc::new(SpinLock::new(0));
+        let thread = thread::spawn({
+            let lock = Arc::clone(&lock);
+            move || *lock.lock().unwrap() += 1
+        });
+        *lock.lock().unwrap() += 1;
+        thread.join().unwrap();
+        assert_eq!(*lock.lock().unwrap(), 2);
+    });
+    assert!(executions > 1);
+}
 
-    println!("Data: {:?}", *DATA.lock());
+#[cfg(feature = "model")]
+#[test]
+fn model_ticket_lock() {
+    use crate::ch6::Arc;
+
+    model::model(|| {
+        let lock = Arc::new(TicketLock::new(0));
+        let thread = thread::spawn({
+            let lock = Arc::clone(&lock);
+            move || *lock.lock().unwrap() += 1
+        });
+        if let Ok(mut guard) = lock.try_lock() {
+            *guard += 1;
+        } else {
+            *lock.lock().unwrap() += 1;
+        }
+        thread.join().unwrap();
+        assert_eq!(*lock.lock().unwrap(), 2);
+        assert_eq!(lock.queue_len(), 0);
+    });
 }
@@ -0,0 +1,80 @@
+//! The poisoning shared by the locks in this module, like the flag inside [std::sync::Mutex].
+//! A lock wraps its guard with [Flag::guard] right after locking, and the guard calls [Flag::done] right before unlocking.
+
+use super::*;
+
+/// Whether a thread panicked while holding the lock, so `T` might be half-mutated.
+pub(crate) struct Flag {
+    is_poisoned: AtomicBool,
+}
+
+/// Remembers whether the thread was already panicking when it locked.
+/// A guard created while already panicking (ie in a destructor) shouldn't poison the lock again.
+pub(crate) struct PanicCheck {
+    was_panicking: bool,
+}
+
+impl Flag {
+    pub(crate) const fn new() -> Self {
+        return Self {
+            is_poisoned: AtomicBool::new(false),
+        };
+    }
+    /// Creates the guard of an already locked lock with `new_guard`, and wraps it into a [LockResult] depending on [Flag::get].
+    pub(crate) fn guard<G>(&self, new_guard: impl FnOnce(PanicCheck) -> G) -> LockResult<G> {
+        let guard = new_guard(PanicCheck {
+            was_panicking: thread::panicking(),
+        });
+        return match self.get() {
+            true => Err(PoisonError::new(guard)),
+            false => Ok(guard),
+        };
+    }
+    /// Poisons the lock if the thread started panicking while holding it. Call this before unlocking.
+    pub(crate) fn done(&self, check: &PanicCheck) {
+        // the guard is being dropped while unwinding. the T might be in an invalid state
+        if !check.was_panicking && thread::panicking() {
+            self.is_poisoned.store(true, Relaxed);
+        }
+    }
+    pub(crate) fn get(&self) -> bool {
+        // Relaxed is enough: the flag is written before the Release unlock and read after the Acquire lock
+        return self.is_poisoned.load(Relaxed);
+    }
+    pub(crate) fn clear(&self) {
+        self.is_poisoned.store(false, Relaxed);
+    }
+    /// Wraps the `T` taken out of a consumed lock into a [LockResult] depending on [Flag::get].
+    pub(crate) fn result<T>(&self, value: T) -> LockResult<T> {
+        return match self.get() {
+            true => Err(PoisonError::new(value)),
+            false => Ok(value),
+        };
+    }
+}
+
+/// Implements [Deref](std::ops::Deref), [DerefMut](std::ops::DerefMut) and [Debug](std::fmt::Debug) as `T` for a guard
+/// with an `inner` field pointing at its lock, which keeps the `T` in a `value: UnsafeCell<T>` field.
+macro_rules! guard_deref {
+    ($guard:ident) => {
+        impl<T> std::ops::Deref for $guard<'_, T> {
+            type Target = T;
+            fn deref(&self) -> &T {
+                // SAFETY: a guard only exists while its thread holds the lock
+                return unsafe { &*self.inner.value.get() };
+            }
+        }
+        impl<T> std::ops::DerefMut for $guard<'_, T> {
+            fn deref_mut(&mut self) -> &mut T {
+                // SAFETY: a guard only exists while its thread holds the lock
+                return unsafe { &mut *self.inner.value.get() };
+            }
+        }
+        impl<T: std::fmt::Debug> std::fmt::Debug for $guard<'_, T> {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                return std::fmt::Debug::fmt(&**self, f);
+            }
+        }
+    };
+}
+pub(crate) use guard_deref;
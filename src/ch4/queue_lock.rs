@@ -0,0 +1,351 @@
+//! Queue locks: every waiter spins on its own node instead of on the lock, so waiting doesn't bounce a shared cache line between cores.
+//! - [McsLock]: a waiter links its node behind the previous tail, and the previous holder hands the lock over by writing to that node.
+//! - [ClhLock]: a waiter spins on the node of the thread before it, which marks its own node when it unlocks.
+//!
+//! Both are first come, first served like [super::TicketLock], and poisoned like [SpinLock].
+//! Every [McsLock::lock] and [ClhLock::lock] allocates a node, which is freed once nobody can look at it anymore.
+
+use std::ptr::NonNull;
+
+use super::*;
+use crate::cache_padded::CachePadded;
+
+/// Waits until `is_locked` is false, with `backoff` between the checks.
+fn wait_until_unlocked(is_locked: &AtomicBool, backoff: Backoff) {
+    let mut snooze = backoff.start();
+    // Acquire: happens-after the previous holder's unlock
+    while is_locked.load(Acquire) {
+        snooze.snooze();
+    }
+}
+
+/// The node of a thread waiting for (or holding) an [McsLock].
+struct McsNode {
+    /// The node of the thread that locked after this one, linked in by that thread.
+    next: AtomicPtr<CachePadded<McsNode>>,
+    /// Cleared by the previous holder to hand the lock over.
+    is_locked: AtomicBool,
+}
+
+/// The Mellor-Crummey Scott lock. The lock itself is only a pointer to the last node in the queue.
+pub struct McsLock<T> {
+    /// The node of the last thread in the queue, null when unlocked.
+    tail: AtomicPtr<CachePadded<McsNode>>,
+    backoff: Backoff,
+    poison: poison::Flag,
+    value: UnsafeCell<T>,
+}
+unsafe impl<T: Send> Sync for McsLock<T> {}
+impl<T> McsLock<T> {
+    /// Uses [Backoff::SpinThenYield], see [super::TicketLock::new].
+    pub const fn new(value: T) -> Self {
+        return Self::with_backoff(value, Backoff::SpinThenYield);
+    }
+    pub const fn with_backoff(value: T, backoff: Backoff) -> Self {
+        return Self {
+            tail: AtomicPtr::new(ptr::null_mut()),
+            backoff,
+            poison: poison::Flag::new(),
+            value: UnsafeCell::new(value),
+        };
+    }
+    fn new_node() -> NonNull<CachePadded<McsNode>> {
+        let node = Box::new(CachePadded(McsNode {
+            next: AtomicPtr::new(ptr::null_mut()),
+            is_locked: AtomicBool::new(true),
+        }));
+        return NonNull::from(Box::leak(node));
+    }
+    /// # Errors
+    /// - When another thread panicked while holding an [McsGuard]. The [PoisonError] still contains the [McsGuard]
+    pub fn lock(&self) -> LockResult<McsGuard<'_, T>> {
+        let node = Self::new_node();
+        // AcqRel: Acquire for when the lock was free and the previous holder released it by resetting tail.
+        // Release so the previous tail's thread sees our initialized node
+        let previous = self.tail.swap(node.as_ptr(), AcqRel);
+        if let Some(previous) = NonNull::new(previous) {
+            // SAFETY: the previous node is only freed after its thread handed the lock to us, which needs this link
+            unsafe { previous.as_ref() }
+                .next
+                .store(node.as_ptr(), Release);
+            // SAFETY: our own node lives until our guard is dropped
+            wait_until_unlocked(&unsafe { node.as_ref() }.is_locked, self.backoff);
+        }
+        return self.guard(node);
+    }
+    /// # Errors
+    /// - [TryLockError::WouldBlock] when the [McsLock] is locked. This method doesn't wait
+    /// - [TryLockError::Poisoned] like [McsLock::lock]
+    pub fn try_lock(&self) -> TryLockResult<McsGuard<'_, T>> {
+        let node = Self::new_node();
+        if self
+            .tail
+            .compare_exchange(ptr::null_mut(), node.as_ptr(), Acquire, Relaxed)
+            .is_err()
+        {
+            // SAFETY: nobody else saw the node
+            drop(unsafe { Box::from_raw(node.as_ptr()) });
+            return Err(TryLockError::WouldBlock);
+        }
+        return Ok(self.guard(node)?);
+    }
+    fn guard(&self, node: NonNull<CachePadded<McsNode>>) -> LockResult<McsGuard<'_, T>> {
+        return self.poison.guard(|panic| McsGuard {
+            inner: self,
+            node,
+            panic,
+        });
+    }
+    pub fn is_poisoned(&self) -> bool {
+        return self.poison.get();
+    }
+    /// Marks the [McsLock] as not poisoned. Call this after restoring `T` to a valid state.
+    pub fn clear_poison(&self) {
+        self.poison.clear();
+    }
+    /// # Errors
+    /// - When the [McsLock] is poisoned. The [PoisonError] still contains the `T`
+    pub fn into_inner(self) -> LockResult<T> {
+        return self.poison.result(self.value.into_inner());
+    }
+}
+
+/// Exclusive access to the `T` of an [McsLock]. Dropping it hands the lock to the next thread in the queue.
+pub struct McsGuard<'a, T> {
+    inner: &'a McsLock<T>,
+    /// Our node, freed when the lock is handed over.
+    node: NonNull<CachePadded<McsNode>>,
+    panic: poison::PanicCheck,
+}
+unsafe impl<T: Sync> Sync for McsGuard<'_, T> {}
+impl<T> Drop for McsGuard<'_, T> {
+    fn drop(&mut self) {
+        self.inner.poison.done(&self.panic);
+
+        // SAFETY: our node is only freed below
+        let node = unsafe { self.node.as_ref() };
+        let mut next = node.next.load(Acquire);
+        if next.is_null() {
+            // nobody is waiting: unlock by emptying the queue
+            if self
+                .inner
+                .tail
+                .compare_exchange(self.node.as_ptr(), ptr::null_mut(), Release, Relaxed)
+                .is_ok()
+            {
+                // SAFETY: the node isn't in the queue anymore
+                drop(unsafe { Box::from_raw(self.node.as_ptr()) });
+                return;
+            }
+            // a thread swapped itself into tail, but hasn't linked its node to ours yet
+            let mut snooze = Backoff::Spin.start();
+            loop {
+                next = node.next.load(Acquire);
+                if !next.is_null() {
+                    break;
+                }
+                snooze.snooze();
+            }
+        }
+
+        // SAFETY: the next node is only freed after its thread gets the lock from us
+        unsafe { &*next }.is_locked.store(false, Release);
+        // SAFETY: the next thread linked in its node, nothing looks at ours anymore
+        drop(unsafe { Box::from_raw(self.node.as_ptr()) });
+    }
+}
+impl<'a, T> crate::ch9::CondvarGuard<'a> for McsGuard<'a, T> {
+    type Lock = McsLock<T>;
+    fn unlock(self) -> &'a McsLock<T> {
+        return self.inner;
+    }
+    fn relock(lock: &'a McsLock<T>) -> LockResult<Self> {
+        return lock.lock();
+    }
+}
+poison::guard_deref!(McsGuard);
+
+/// The node of a thread waiting for (or holding) a [ClhLock].
+/// `is_locked` is cleared when the thread unlocks, which is what the next thread spins on.
+type ClhNode = CachePadded<AtomicBool>;
+
+/// The Craig, Landin and Hagersten lock. The queue is implicit: every waiter only knows the node of the thread before it.
+///
+/// Unlike [McsLock], unlocking never waits for the next thread. But a waiter can't leave the queue, so there is no `try_lock`.
+pub struct ClhLock<T> {
+    /// The node of the last thread in the queue. Starts as an unlocked node, so the first thread doesn't wait.
+    tail: AtomicPtr<ClhNode>,
+    backoff: Backoff,
+    poison: poison::Flag,
+    value: UnsafeCell<T>,
+}
+unsafe impl<T: Send> Sync for ClhLock<T> {}
+impl<T> ClhLock<T> {
+    /// Uses [Backoff::SpinThenYield], see [super::TicketLock::new].
+    pub fn new(value: T) -> Self {
+        return Self::with_backoff(value, Backoff::SpinThenYield);
+    }
+    /// Not `const` like the other locks, because the first node is allocated right away.
+    pub fn with_backoff(value: T, backoff: Backoff) -> Self {
+        return Self {
+            tail: AtomicPtr::new(Self::new_node(false).as_ptr()),
+            backoff,
+            poison: poison::Flag::new(),
+            value: UnsafeCell::new(value),
+        };
+    }
+    fn new_node(is_locked: bool) -> NonNull<ClhNode> {
+        return NonNull::from(Box::leak(Box::new(CachePadded(AtomicBool::new(is_locked)))));
+    }
+    /// # Errors
+    /// - When another thread panicked while holding a [ClhGuard]. The [PoisonError] still contains the [ClhGuard]
+    pub fn lock(&self) -> LockResult<ClhGuard<'_, T>> {
+        let node = Self::new_node(true);
+        // AcqRel: Release so the next thread sees our initialized node, Acquire to see the previous node initialized
+        let previous = self.tail.swap(node.as_ptr(), AcqRel);
+        // SAFETY: we are the only thread looking at the previous node, it is freed by our guard
+        wait_until_unlocked(unsafe { &*previous }, self.backoff);
+        return self.poison.guard(|panic| ClhGuard {
+            inner: self,
+            node,
+            // SAFETY: swap never returns null, tail always points at a node
+            previous: unsafe { NonNull::new_unchecked(previous) },
+            panic,
+        });
+    }
+    pub fn is_poisoned(&self) -> bool {
+        return self.poison.get();
+    }
+    /// Marks the [ClhLock] as not poisoned. Call this after restoring `T` to a valid state.
+    pub fn clear_poison(&self) {
+        self.poison.clear();
+    }
+    /// # Errors
+    /// - When the [ClhLock] is poisoned. The [PoisonError] still contains the `T`
+    pub fn into_inner(self) -> LockResult<T> {
+        // Drop frees the last node, and can't run after a field is moved out
+        let this = std::mem::ManuallyDrop::new(self);
+        // SAFETY: `this` is never used or dropped again
+        drop(unsafe { Box::from_raw(this.tail.load(Relaxed)) });
+        let value = unsafe { ptr::read(&this.value) }.into_inner();
+        return this.poison.result(value);
+    }
+}
+impl<T> Drop for ClhLock<T> {
+    fn drop(&mut self) {
+        // SAFETY: the last node belongs to the lock once its thread unlocked, and no thread holds the lock now
+        drop(unsafe { Box::from_raw(*self.tail.get_mut()) });
+    }
+}
+
+/// Exclusive access to the `T` of a [ClhLock]. Dropping it lets the next thread in the queue continue.
+pub struct ClhGuard<'a, T> {
+    inner: &'a ClhLock<T>,
+    /// Our node, which the next thread spins on. Freed by the next thread, or by the [ClhLock] if there is none.
+    node: NonNull<ClhNode>,
+    /// The node of the thread before us, which nobody looks at anymore.
+    previous: NonNull<ClhNode>,
+    panic: poison::PanicCheck,
+}
+unsafe impl<T: Sync> Sync for ClhGuard<'_, T> {}
+impl<T> Drop for ClhGuard<'_, T> {
+    fn drop(&mut self) {
+        self.inner.poison.done(&self.panic);
+        // SAFETY: the previous thread is done with its node, and we were the only thread spinning on it
+        drop(unsafe { Box::from_raw(self.previous.as_ptr()) });
+        // SAFETY: our node is freed by whoever comes after us, which only happens after this store
+        unsafe { self.node.as_ref() }.store(false, Release);
+    }
+}
+impl<'a, T> crate::ch9::CondvarGuard<'a> for ClhGuard<'a, T> {
+    type Lock = ClhLock<T>;
+    fn unlock(self) -> &'a ClhLock<T> {
+        return self.inner;
+    }
+    fn relock(lock: &'a ClhLock<T>) -> LockResult<Self> {
+        return lock.lock();
+    }
+}
+poison::guard_deref!(ClhGuard);
+
+#[test]
+fn queue_locks_count() {
+    let thread_count = 8;
+    let increments_per_thread = 1000;
+
+    let mcs_lock = McsLock::new(0);
+    let clh_lock = ClhLock::new(0);
+    thread::scope(|s| {
+        for _ in 0..thread_count {
+            s.spawn(|| {
+                for _ in 0..increments_per_thread {
+                    *mcs_lock.lock().unwrap() += 1;
+                    *clh_lock.lock().unwrap() += 1;
+                }
+            });
+        }
+    });
+
+    assert_eq!(
+        mcs_lock.into_inner().unwrap(),
+        thread_count * increments_per_thread
+    );
+    assert_eq!(
+        clh_lock.into_inner().unwrap(),
+        thread_count * increments_per_thread
+    );
+}
+
+#[test]
+fn queue_locks_poison_and_try_lock() {
+    let mcs_lock = McsLock::new(0);
+    let clh_lock = ClhLock::new(String::new());
+
+    let guard = mcs_lock.try_lock().unwrap();
+    assert!(matches!(mcs_lock.try_lock(), Err(TryLockError::WouldBlock)));
+    drop(guard);
+
+    thread::scope(|s| {
+        let result = s
+            .spawn(|| {
+                let _mcs_guard = mcs_lock.lock().unwrap();
+                clh_lock.lock().unwrap().push_str("half mutated");
+                let _clh_guard = clh_lock.lock().unwrap();
+                panic!("poison both locks");
+            })
+            .join();
+        assert!(result.is_err());
+    });
+
+    assert!(mcs_lock.is_poisoned() && clh_lock.is_poisoned());
+    assert!(matches!(
+        mcs_lock.try_lock(),
+        Err(TryLockError::Poisoned(_))
+    ));
+    clh_lock.lock().unwrap_err().into_inner().clear();
+    clh_lock.clear_poison();
+    assert_eq!(*clh_lock.lock().unwrap(), "");
+    assert_eq!(mcs_lock.into_inner().unwrap_err().into_inner(), 0);
+}
+
+#[cfg(feature = "model")]
+#[test]
+fn model_queue_locks() {
+    use crate::ch6::Arc;
+
+    model::model(|| {
+        let locks = Arc::new((McsLock::new(0), ClhLock::new(0)));
+        let thread = thread::spawn({
+            let locks = Arc::clone(&locks);
+            move || {
+                *locks.0.lock().unwrap() += 1;
+                *locks.1.lock().unwrap() += 1;
+            }
+        });
+        *locks.0.lock().unwrap() += 1;
+        *locks.1.lock().unwrap() += 1;
+        thread.join().unwrap();
+        assert_eq!(*locks.0.lock().unwrap(), 2);
+        assert_eq!(*locks.1.lock().unwrap(), 2);
+    });
+}
@@ -1,81 +1,130 @@
 //! Building Our Own Channels Summary
 //! - A channel is used to send messages between threads.
 //! - A simple and flexible, but potentially inefficient, channel is relatively easy to implement
-//! with just a Mutex and a Condvar.
+//!   with just a Mutex and a Condvar.
 //! - A one-shot channel is a channel designed to send only one message.
 //! - The MaybeUninit<T> type can be used to represent a potentially not-yet-initialized T.
-//! Its interface is mostly unsafe, making its user responsible for tracking whether it has been initialized,
-//! not duplicating non-Copy data, and dropping its contents if necessary.
+//!   Its interface is mostly unsafe, making its user responsible for tracking whether it has been initialized,
+//!   not duplicating non-Copy data, and dropping its contents if necessary.
 //! - Not dropping objects (also called leaking or forgetting) is safe, but frowned upon when done without good reason.
 //! - Panicking is an important tool for creating a safe interface.
 //! - Taking a non-Copy object by value can be used to prevent something from being done more than once.
 //! - Exclusively borrowing and splitting borrows can be a powerful tool for forcing correctness.
 //! - We can make sure an object stays on the same thread by making sure its type does not implement Send,
-//! which can be achieved with the PhantomData marker type.
+//!   which can be achieved with the PhantomData marker type.
 //! - Every design and implementation decision involves a trade-off and can best be made with a specific use case in mind.
 //! - Designing something without a use case can be fun and educational, but can turn out to be an endless task.
 
-use super::*;
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
 
-pub struct SimpleChannel<T> {
-    queue: Mutex<VecDeque<T>>,
-    message_ready: Condvar,
+use super::*;
+use crate::{
+    ch6::Arc,
+    ch9::{Condvar, Mutex},
+    executor::{block_on, thread_waker},
+};
+
+pub mod borrowed;
+pub mod error;
+pub mod mpmc;
+pub mod simple;
+pub mod spsc;
+pub use error::{RecvError, RecvTimeoutError, SendError, TryRecvError, TrySendError};
+pub use simple::{channel as simple_channel, SimpleChannel};
+
+/// A place for a single message, shared by every oneshot channel in this module.
+/// Tracks whether the message is initialized, and drops it if it was never taken.
+struct MessageSlot<T> {
+    message: UnsafeCell<MaybeUninit<T>>,
+    is_message_ready: AtomicBool,
 }
-impl<T> SimpleChannel<T> {
-    pub fn new() -> Self {
+
+unsafe impl<T> Sync for MessageSlot<T> where T: Send {}
+
+impl<T> MessageSlot<T> {
+    const fn new() -> Self {
         Self {
-            queue: Mutex::default(),
-            message_ready: Condvar::new(),
+            message: UnsafeCell::new(MaybeUninit::uninit()),
+            is_message_ready: AtomicBool::new(false),
         }
     }
 
-    pub fn send(&mut self, message: T) -> Result<(), PoisonError<MutexGuard<VecDeque<T>>>> {
-        // add the message to the queue
-        self.queue.lock()?.push_front(message);
+    fn is_message_ready(&self) -> bool {
+        self.is_message_ready.load(Relaxed)
+    }
 
-        // Notify a blocked thread that a message is ready
-        self.message_ready.notify_one();
+    /// # Safety
+    /// - Only call this method once!
+    unsafe fn write(&self, message: T) {
+        let maybe_uninit_message = &mut *self.message.get();
+        maybe_uninit_message.write(message);
 
-        Ok(())
+        // Notify that a message is ready
+        self.is_message_ready.store(true, Release);
     }
 
-    pub fn receive(&mut self) -> Result<T, PoisonError<MutexGuard<VecDeque<T>>>> {
-        // lock the queue
-        let mut guard = self.queue.lock()?;
+    /// Takes the message if it is ready.
+    fn take(&self) -> Option<T> {
+        // sets the message ready flag to false so the message can only be taken once (and isn't dropped by MessageSlot::drop)
+        if !self.is_message_ready.swap(false, Acquire) {
+            return None;
+        }
 
-        // receiving loop
-        loop {
-            // check if there is a message in the queue
-            match guard.pop_front() {
-                // return the message
-                Some(message) => return Ok(message),
+        // Safety: the message is initialized because is_message_ready was true
+        Some(unsafe { (*self.message.get()).assume_init_read() })
+    }
 
-                // or wait for the message to be ready
-                None => guard = self.message_ready.wait(guard)?,
-            }
+    /// # Safety
+    /// - Only call this method after [MessageSlot::is_message_ready] returns `true`
+    /// - Only call this method once
+    unsafe fn take_unchecked(&self) -> T {
+        // the acquire-fence + Relaxed-load in is_message_ready happens-after the release-store in MessageSlot::write
+        fence(Acquire);
+
+        // the message is moved out, so MessageSlot::drop must not drop it again
+        self.is_message_ready.store(false, Relaxed);
+
+        let maybe_uninit_message = &*self.message.get();
+        maybe_uninit_message.assume_init_read()
+    }
+}
+
+impl<T> Drop for MessageSlot<T> {
+    fn drop(&mut self) {
+        if *self.is_message_ready.get_mut() {
+            unsafe { self.message.get_mut().assume_init_drop() }
         }
     }
 }
 
 pub struct OneshotChannel<T> {
-    message: UnsafeCell<MaybeUninit<T>>,
+    slot: MessageSlot<T>,
     is_message_in_use: AtomicBool,
-    is_message_ready: AtomicBool,
+    /// The task awaiting [OneshotChannel::receive_async], woken up when the message is sent.
+    receiving_waker: AtomicWaker,
 }
 
-unsafe impl<T> Sync for OneshotChannel<T> where T: Send {}
+impl<T> Default for OneshotChannel<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl<T> OneshotChannel<T> {
     pub const fn new() -> Self {
         Self {
-            message: UnsafeCell::new(MaybeUninit::uninit()),
+            slot: MessageSlot::new(),
             is_message_in_use: AtomicBool::new(false),
-            is_message_ready: AtomicBool::new(false),
+            receiving_waker: AtomicWaker::new(),
         }
     }
 
     pub fn is_message_ready(&self) -> bool {
-        self.is_message_ready.load(Relaxed)
+        self.slot.is_message_ready()
     }
 
     /// [OneshotChannel] can only [OneshotChannel::send] one message.
@@ -89,13 +138,9 @@ impl<T> OneshotChannel<T> {
         }
 
         // Safety: the channel message can't be in use because of the panic
-        unsafe {
-            let channel_message = &mut *self.message.get();
-            channel_message.write(message);
-        }
+        unsafe { self.slot.write(message) };
 
-        // notify the message is ready
-        self.is_message_ready.store(true, Release);
+        self.receiving_waker.wake();
     }
 
     /// Use [OneshotChannel::is_message_ready] to be sure to [OneshotChannel::receive] won't panic
@@ -104,14 +149,34 @@ impl<T> OneshotChannel<T> {
     pub fn receive(&self) -> T {
         // sets the message ready flag to false
         // panics if the value was already false
-        if !self.is_message_ready.swap(false, Acquire) {
-            panic!("The message was not ready. Be sure to check OneshotChannel::is_message_ready before calling OneshotChannel::receive");
+        match self.slot.take() {
+            Some(message) => message,
+            None => panic!("The message was not ready. Be sure to check OneshotChannel::is_message_ready before calling OneshotChannel::receive"),
         }
+    }
+
+    /// Returns a future that resolves to the message once it is sent, instead of panicking when it isn't ready.
+    /// Only one receiver can be woken up, so the message should only be awaited from one place at a time.
+    pub fn receive_async(&self) -> Receive<'_, T> {
+        Receive { channel: self }
+    }
+}
+
+/// The [Future] returned by [OneshotChannel::receive_async].
+pub struct Receive<'a, T> {
+    channel: &'a OneshotChannel<T>,
+}
 
-        // Safety: The message is initialized at this point because of the panic
-        unsafe {
-            let channel_message = &*self.message.get();
-            channel_message.assume_init_read()
+impl<T> Future for Receive<'_, T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        // register before checking, so a send that happens after the check will wake us
+        self.channel.receiving_waker.register(cx.waker());
+
+        match self.channel.slot.take() {
+            Some(message) => Poll::Ready(message),
+            None => Poll::Pending,
         }
     }
 }
@@ -120,33 +185,21 @@ impl<T> OneshotChannel<T> {
     /// # Safety
     /// - Only call this method once!
     pub unsafe fn send_unchecked(&self, message: T) {
-        let maybe_uninit_message = &mut *self.message.get();
-        maybe_uninit_message.write(message);
-
-        // Notify that a message is ready
-        self.is_message_ready.store(true, Release);
+        self.slot.write(message);
+        self.receiving_waker.wake();
     }
 
-    /// # Safety:
+    /// # Safety
     /// - Only call this method after [OneshotChannel::is_message_ready] returns `true`
     /// - Only call this method once
     pub unsafe fn receive_unchecked(&self) -> T {
-        let maybe_uninit_message = &*self.message.get();
-        maybe_uninit_message.assume_init_read()
-    }
-}
-
-impl<T> Drop for OneshotChannel<T> {
-    fn drop(&mut self) {
-        if *self.is_message_ready.get_mut() {
-            unsafe { self.message.get_mut().assume_init_drop() }
-        }
+        self.slot.take_unchecked()
     }
 }
 
 #[test]
 fn oneshot_channel_drop() {
-    const MESSAGE: &'static str = "Message text";
+    const MESSAGE: &str = "Message text";
     let channel = OneshotChannel::new();
     let current_thread = thread::current();
 
@@ -164,10 +217,59 @@ fn oneshot_channel_drop() {
     });
 }
 
+#[test]
+fn oneshot_channel_receive_async() {
+    let channel = OneshotChannel::new();
+
+    thread::scope(|s| {
+        s.spawn(|| {
+            thread::sleep(Duration::from_millis(50));
+            channel.send(1);
+        });
+
+        assert_eq!(block_on(channel.receive_async()), 1);
+    });
+
+    assert!(!channel.is_message_ready());
+}
+
+#[cfg(feature = "model")]
+#[test]
+fn model_oneshot_channel() {
+    model::model(|| {
+        let channel = Arc::new(OneshotChannel::new());
+        let receiver = thread::current();
+        thread::spawn({
+            let channel = Arc::clone(&channel);
+            move || {
+                channel.send(String::from("hello"));
+                receiver.unpark();
+            }
+        });
+
+        while !channel.is_message_ready() {
+            thread::park();
+        }
+        assert_eq!(channel.receive(), "hello");
+    });
+
+    // the message is taken right after registering the waker, or after being woken up
+    model::model(|| {
+        let channel = Arc::new(OneshotChannel::new());
+        thread::spawn({
+            let channel = Arc::clone(&channel);
+            move || channel.send(String::from("hello"))
+        });
+        assert_eq!(block_on(channel.receive_async()), "hello");
+    });
+}
+
 pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
     let channel = Arc::new(Channel {
-        message: UnsafeCell::new(MaybeUninit::uninit()),
-        is_message_ready: AtomicBool::new(false),
+        slot: MessageSlot::new(),
+        is_sender_dropped: AtomicBool::new(false),
+        is_receiver_dropped: AtomicBool::new(false),
+        receiving_waker: AtomicWaker::new(),
     });
 
     let sender = Sender {
@@ -180,14 +282,90 @@ pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
 }
 
 struct Channel<T> {
-    message: UnsafeCell<MaybeUninit<T>>,
-    is_message_ready: AtomicBool,
+    slot: MessageSlot<T>,
+    /// Set when the [Sender] is dropped, either after [Sender::send] or without sending.
+    is_sender_dropped: AtomicBool,
+    /// Set when the [Receiver] is dropped, see [Sender::is_closed].
+    is_receiver_dropped: AtomicBool,
+    /// The thread blocked in [Receiver::recv] or the task awaiting the [Receiver], woken up when the [Sender] is dropped.
+    receiving_waker: AtomicWaker,
 }
-unsafe impl<T> Sync for Channel<T> where T: Send {}
-impl<T> Drop for Channel<T> {
-    fn drop(&mut self) {
-        if *self.is_message_ready.get_mut() {
-            unsafe { self.message.get_mut().assume_init_drop() };
+
+/// A slot for the [Waker] of whoever waits for the message, which can be (re)registered and woken up concurrently.
+/// This is the same protocol as `AtomicWaker` from the futures crate:
+/// [AtomicWaker::register] and [AtomicWaker::wake] both do a read-modify-write on `state`,
+/// and those are totally ordered, so either the register sees the wake or the wake sees the registered waker.
+///
+/// A blocked thread registers [crate::executor::thread_waker], a future registers the waker from its [Context].
+struct AtomicWaker {
+    state: AtomicU8,
+    waker: UnsafeCell<Option<Waker>>,
+}
+// SAFETY: `waker` is only accessed by whoever moved `state` away from WAITING
+unsafe impl Sync for AtomicWaker {}
+impl AtomicWaker {
+    /// Nobody is accessing `waker`.
+    const WAITING: u8 = 0;
+    /// [AtomicWaker::register] is writing `waker`.
+    const REGISTERING: u8 = 1;
+    /// [AtomicWaker::wake] is taking `waker` (or will, once registering is done).
+    const WAKING: u8 = 2;
+
+    const fn new() -> Self {
+        Self {
+            state: AtomicU8::new(Self::WAITING),
+            waker: UnsafeCell::new(None),
+        }
+    }
+
+    fn register(&self, waker: &Waker) {
+        match self
+            .state
+            .compare_exchange(Self::WAITING, Self::REGISTERING, Acquire, Acquire)
+        {
+            Ok(_) => {
+                // SAFETY: we moved the state from WAITING to REGISTERING, so we have exclusive access
+                let registered = unsafe { &mut *self.waker.get() };
+                // polling again with the same waker is common, don't clone it every time
+                if !registered.as_ref().is_some_and(|r| r.will_wake(waker)) {
+                    *registered = Some(waker.clone());
+                }
+
+                // Release so AtomicWaker::wake sees the waker
+                if let Err(actual) =
+                    self.state
+                        .compare_exchange(Self::REGISTERING, Self::WAITING, AcqRel, Acquire)
+                {
+                    // AtomicWaker::wake was called while we were registering. it left the waker for us to wake
+                    debug_assert_eq!(actual, Self::REGISTERING | Self::WAKING);
+                    // SAFETY: wake doesn't touch the waker while REGISTERING is set
+                    let waker = unsafe { (*self.waker.get()).take() };
+                    self.state.swap(Self::WAITING, AcqRel);
+                    if let Some(waker) = waker {
+                        waker.wake();
+                    }
+                }
+            }
+            // a wake is in progress, so whatever we are waiting for already happened.
+            // or another thread is registering at the same time; wake so the caller checks again and retries.
+            // a blocked thread unparks itself and comes right back, so give the other thread a moment to finish first
+            Err(_) => {
+                hint::spin_loop();
+                waker.wake_by_ref();
+            }
+        }
+    }
+
+    /// Wakes the registered waker, if any.
+    fn wake(&self) {
+        // if a register or another wake is in progress, register will see WAKING and wake the waker itself
+        if self.state.fetch_or(Self::WAKING, AcqRel) == Self::WAITING {
+            // SAFETY: we moved the state from WAITING to WAKING, so we have exclusive access
+            let waker = unsafe { (*self.waker.get()).take() };
+            self.state.fetch_and(!Self::WAKING, Release);
+            if let Some(waker) = waker {
+                waker.wake();
+            }
         }
     }
 }
@@ -202,40 +380,281 @@ pub struct Receiver<T> {
 impl<T> Sender<T> {
     pub fn send(self, message: T) {
         // Safety: This method take ownership of self (Self is not Copy) so the message can't be initialized more than once
-        unsafe { (*self.channel.message.get()).write(message) };
+        unsafe { self.channel.slot.write(message) };
+
+        // self is dropped here, which wakes up the receiver
+    }
 
-        self.channel.is_message_ready.store(true, Release);
+    /// Returns `true` when the [Receiver] was dropped, so a message would never be received.
+    pub fn is_closed(&self) -> bool {
+        self.channel.is_receiver_dropped.load(Relaxed)
+    }
+}
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        // Release so a receiver that sees this flag also sees the message, if it was sent
+        self.channel.is_sender_dropped.store(true, Release);
+
+        // wake up the receiver if it is blocked in Receiver::recv or awaited, either for the message or to report the drop
+        self.channel.receiving_waker.wake();
+    }
+}
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.channel.is_receiver_dropped.store(true, Relaxed);
     }
 }
 impl<T> Receiver<T> {
     pub fn is_message_ready(&self) -> bool {
-        self.channel.is_message_ready.load(Relaxed)
+        self.channel.slot.is_message_ready()
+    }
+
+    /// Blocks until the message is sent.
+    /// # Errors
+    /// - [RecvError] when the [Sender] was dropped without sending
+    pub fn recv(self) -> Result<T, RecvError> {
+        // the Receiver is also a Future, so blocking is just awaiting it on this thread
+        block_on(self)
     }
-    pub fn receive(self) -> T {
-        if !self.channel.is_message_ready.swap(false, Acquire) {
-            panic!("Message is not ready! Be sure to check Receiver::is_message_ready before calling Receiver::receive");
+
+    /// # Errors
+    /// - [TryRecvError::Empty] instead of waiting for the message
+    /// - [TryRecvError::Disconnected] when the [Sender] was dropped without sending
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        // check the flag before the message: the sender sets it after sending,
+        // so if it is set and there is no message, there never will be
+        let is_sender_dropped = self.channel.is_sender_dropped.load(Acquire);
+
+        match self.channel.slot.take() {
+            Some(message) => Ok(message),
+            None if is_sender_dropped => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
         }
+    }
+
+    /// Like [Receiver::recv] but gives up after `timeout`.
+    /// # Errors
+    /// - [RecvTimeoutError::Timeout] when the message wasn't sent in time
+    /// - [RecvTimeoutError::Disconnected] when the [Sender] was dropped without sending
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        // a deadline too far away to represent is never reached
+        let deadline = Instant::now().checked_add(timeout);
+        let waker = thread_waker();
+        loop {
+            // register before checking, so a send that happens after the check will unpark us
+            self.channel.receiving_waker.register(&waker);
+
+            match self.try_recv() {
+                Ok(message) => return Ok(message),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {}
+            }
 
-        unsafe { (*self.channel.message.get()).assume_init_read() }
+            let Some(deadline) = deadline else {
+                thread::park();
+                continue;
+            };
+            // park_timeout can return early, so keep track of the time that is left
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            thread::park_timeout(remaining);
+        }
+    }
+}
+
+/// Awaiting the [Receiver] receives the message, like [Receiver::recv] without blocking the thread.
+impl<T> Future for Receiver<T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // register before checking, so a send that happens after the check will wake us
+        self.channel.receiving_waker.register(cx.waker());
+
+        match self.try_recv() {
+            Ok(message) => Poll::Ready(Ok(message)),
+            Err(TryRecvError::Disconnected) => Poll::Ready(Err(RecvError)),
+            Err(TryRecvError::Empty) => Poll::Pending,
+        }
     }
 }
 
 #[test]
 fn split_channel_drop() {
-    const MESSAGE: &'static str = "Message text";
+    const MESSAGE: &str = "Message text";
     let (sender, receiver) = channel();
-    let current_thread = thread::current();
 
     thread::scope(|s| {
         s.spawn(|| {
             sender.send(MESSAGE);
-            current_thread.unpark();
         });
 
-        while !receiver.is_message_ready() {
-            thread::park();
+        // the sender doesn't need to know this thread to wake it up
+        assert_eq!(receiver.recv(), Ok(MESSAGE));
+    });
+}
+
+#[test]
+fn split_channel_recv_from_another_thread() {
+    let (sender, receiver) = channel();
+
+    // the receiving thread is only known once it calls recv
+    let receiving_thread = thread::spawn(move || receiver.recv());
+
+    thread::sleep(Duration::from_millis(50));
+    sender.send(vec![1, 2, 3]);
+
+    assert_eq!(receiving_thread.join().unwrap(), Ok(vec![1, 2, 3]));
+}
+
+#[test]
+fn split_channel_try_recv_and_recv_timeout() {
+    let (sender, receiver) = channel();
+
+    assert_eq!(receiver.try_recv(), Err(TryRecvError::Empty));
+
+    let start = Instant::now();
+    assert_eq!(
+        receiver.recv_timeout(Duration::from_millis(50)),
+        Err(RecvTimeoutError::Timeout)
+    );
+    assert!(start.elapsed() >= Duration::from_millis(50));
+
+    thread::scope(|s| {
+        s.spawn(|| {
+            thread::sleep(Duration::from_millis(10));
+            sender.send(1);
+        });
+        // too long to add to Instant::now(), waits without a deadline
+        assert_eq!(receiver.recv_timeout(Duration::MAX), Ok(1));
+    });
+
+    // the message can only be received once
+    assert_eq!(receiver.try_recv(), Err(TryRecvError::Disconnected));
+}
+
+#[test]
+fn split_channel_sender_dropped() {
+    static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+
+    struct DetectDrop;
+    impl Drop for DetectDrop {
+        fn drop(&mut self) {
+            NUM_DROPS.fetch_add(1, Relaxed);
         }
+    }
+
+    // the sender is dropped without sending while the receiver is blocked
+    let (sender, receiver) = channel::<DetectDrop>();
+    thread::scope(|s| {
+        let receiving_thread = s.spawn(move || receiver.recv());
+        thread::sleep(Duration::from_millis(50));
+        drop(sender);
+        assert!(matches!(receiving_thread.join().unwrap(), Err(RecvError)));
+    });
+
+    // the sender is dropped before the receiver looks
+    let (sender, receiver) = channel::<DetectDrop>();
+    drop(sender);
+    assert!(matches!(
+        receiver.try_recv(),
+        Err(TryRecvError::Disconnected)
+    ));
+    assert!(matches!(
+        receiver.recv_timeout(Duration::from_secs(10)),
+        Err(RecvTimeoutError::Disconnected)
+    ));
+    assert!(matches!(receiver.recv(), Err(RecvError)));
+
+    // a message sent before the sender is dropped is still received
+    let (sender, receiver) = channel();
+    sender.send(DetectDrop);
+    assert_eq!(NUM_DROPS.load(Relaxed), 0);
+    drop(receiver.recv().unwrap());
+    assert_eq!(NUM_DROPS.load(Relaxed), 1);
+}
+
+#[test]
+fn split_channel_receiver_dropped() {
+    static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+
+    struct DetectDrop;
+    impl Drop for DetectDrop {
+        fn drop(&mut self) {
+            NUM_DROPS.fetch_add(1, Relaxed);
+        }
+    }
+
+    // the receiver is dropped first. the sent message is dropped together with the channel
+    let (sender, receiver) = channel();
+    assert!(!sender.is_closed());
+    drop(receiver);
+    assert!(sender.is_closed());
+    sender.send(DetectDrop);
+    assert_eq!(NUM_DROPS.load(Relaxed), 1);
+
+    // the message is sent, but never received
+    let (sender, receiver) = channel();
+    sender.send(DetectDrop);
+    assert_eq!(NUM_DROPS.load(Relaxed), 1);
+    drop(receiver);
+    assert_eq!(NUM_DROPS.load(Relaxed), 2);
+
+    // nothing is sent, and nothing is dropped
+    let (sender, receiver) = channel::<DetectDrop>();
+    drop(receiver);
+    drop(sender);
+    assert_eq!(NUM_DROPS.load(Relaxed), 2);
+}
+
+#[test]
+fn split_channel_await() {
+    let (sender, receiver) = channel();
+
+    thread::scope(|s| {
+        s.spawn(|| {
+            thread::sleep(Duration::from_millis(50));
+            sender.send("hello");
+        });
+
+        let message = block_on(receiver);
+        assert_eq!(message, Ok("hello"));
+    });
+
+    // dropping the sender without sending wakes the task too
+    let (sender, receiver) = channel::<()>();
+    thread::scope(|s| {
+        s.spawn(|| {
+            thread::sleep(Duration::from_millis(50));
+            drop(sender);
+        });
+
+        assert_eq!(block_on(receiver), Err(RecvError));
+    });
+}
+
+#[cfg(feature = "model")]
+#[test]
+fn model_split_channel() {
+    model::model(|| {
+        let (sender, receiver) = channel();
+        thread::spawn(move || sender.send(String::from("hello")));
+        assert_eq!(receiver.recv().as_deref(), Ok("hello"));
+    });
+
+    // the sender is dropped, maybe while the receiver checks for the message
+    model::model(|| {
+        let (sender, receiver) = channel::<String>();
+        thread::spawn(move || drop(sender));
+        assert_eq!(receiver.recv(), Err(RecvError));
+    });
 
-        assert_eq!(receiver.receive(), MESSAGE);
+    // the receiver is dropped before, during or after the send. the message is dropped exactly once
+    model::model(|| {
+        let (sender, receiver) = channel();
+        let thread = thread::spawn(move || sender.send(String::from("hello")));
+        drop(receiver);
+        thread.join().unwrap();
     });
 }
@@ -0,0 +1,129 @@
+//! A oneshot channel that borrows instead of allocating an [Arc].
+//! - The [Channel] lives wherever the caller puts it, usually on the stack.
+//! - [Channel::split] borrows the [Channel] exclusively, so it can't be split again while the [Sender] or [Receiver] exist.
+//! - The [Receiver] is not [Send], so the [Sender] can unpark the thread that called [Channel::split].
+
+use std::marker::PhantomData;
+
+use super::*;
+
+pub struct Channel<T> {
+    slot: MessageSlot<T>,
+}
+
+impl<T> Channel<T> {
+    pub const fn new() -> Self {
+        Self {
+            slot: MessageSlot::new(),
+        }
+    }
+
+    /// Exclusively borrowing self means this can only be called again once the [Sender] and [Receiver] are gone.
+    pub fn split<'a>(&'a mut self) -> (Sender<'a, T>, Receiver<'a, T>) {
+        // reset the channel, dropping the message of a previous split that was never received
+        *self = Self::new();
+
+        let sender = Sender {
+            channel: self,
+            receiving_thread: thread::current(),
+        };
+        let receiver = Receiver {
+            channel: self,
+            _no_send: PhantomData,
+        };
+
+        (sender, receiver)
+    }
+}
+
+impl<T> Default for Channel<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Sender<'a, T> {
+    channel: &'a Channel<T>,
+    /// The thread that called [Channel::split]. The [Receiver] can't leave it.
+    receiving_thread: thread::Thread,
+}
+
+pub struct Receiver<'a, T> {
+    channel: &'a Channel<T>,
+    /// Raw pointers are not [Send], so neither is the [Receiver].
+    _no_send: PhantomData<*const ()>,
+}
+
+impl<T> Sender<'_, T> {
+    pub fn send(self, message: T) {
+        // Safety: This method take ownership of self (Self is not Copy) so the message can't be initialized more than once
+        unsafe { self.channel.slot.write(message) };
+
+        self.receiving_thread.unpark();
+    }
+}
+
+impl<T> Receiver<'_, T> {
+    pub fn is_message_ready(&self) -> bool {
+        self.channel.slot.is_message_ready()
+    }
+
+    /// Blocks until the message is sent.
+    pub fn recv(self) -> T {
+        loop {
+            if let Some(message) = self.channel.slot.take() {
+                return message;
+            }
+
+            // this is the thread the Sender unparks, because the Receiver can't be sent to another thread
+            thread::park();
+        }
+    }
+}
+
+#[test]
+fn borrowed_channel() {
+    let mut channel = Channel::new();
+
+    thread::scope(|s| {
+        let (sender, receiver) = channel.split();
+
+        s.spawn(move || {
+            sender.send("hello world!");
+        });
+
+        assert_eq!(receiver.recv(), "hello world!");
+    });
+
+    // the channel can be reused once the Sender and Receiver are gone
+    let (sender, receiver) = channel.split();
+    sender.send("again");
+    assert!(receiver.is_message_ready());
+    assert_eq!(receiver.recv(), "again");
+}
+
+#[test]
+fn borrowed_channel_drop() {
+    static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+
+    struct DetectDrop;
+    impl Drop for DetectDrop {
+        fn drop(&mut self) {
+            NUM_DROPS.fetch_add(1, Relaxed);
+        }
+    }
+
+    let mut channel = Channel::new();
+
+    // a message that is never received is dropped by the next split
+    let (sender, _receiver) = channel.split();
+    sender.send(DetectDrop);
+    assert_eq!(NUM_DROPS.load(Relaxed), 0);
+    let (sender, _receiver) = channel.split();
+    assert_eq!(NUM_DROPS.load(Relaxed), 1);
+
+    // or together with the channel
+    sender.send(DetectDrop);
+    drop(channel);
+    assert_eq!(NUM_DROPS.load(Relaxed), 2);
+}
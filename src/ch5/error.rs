@@ -0,0 +1,174 @@
+//! The errors of every channel in this chapter, so callers can match on why sending or receiving failed.
+//! - A channel is disconnected when nobody is left on the other side: every receiver is gone for a sender,
+//!   and every sender is gone (or the channel was closed) for a receiver.
+//! - Receiving only fails with `Disconnected` after every message that is still in the channel was received.
+//! - A message that could not be sent is returned in the error.
+
+use std::{error::Error, fmt};
+
+/// The channel is disconnected. Contains the message that could not be sent.
+#[derive(PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+#[derive(PartialEq, Eq)]
+pub enum TrySendError<T> {
+    /// The channel is full. Contains the message that could not be sent.
+    Full(T),
+    /// The channel is disconnected. Contains the message that could not be sent.
+    Disconnected(T),
+}
+
+/// The channel is disconnected and empty.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecvError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryRecvError {
+    /// There is no message yet, but one can still be sent.
+    Empty,
+    /// The channel is disconnected and empty.
+    Disconnected,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecvTimeoutError {
+    /// No message arrived in time.
+    Timeout,
+    /// The channel is disconnected and empty.
+    Disconnected,
+}
+
+impl<T> SendError<T> {
+    /// Takes back the message that could not be sent.
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> TrySendError<T> {
+    /// Takes back the message that could not be sent.
+    pub fn into_inner(self) -> T {
+        match self {
+            Self::Full(message) | Self::Disconnected(message) => message,
+        }
+    }
+}
+
+impl<T> From<SendError<T>> for TrySendError<T> {
+    fn from(SendError(message): SendError<T>) -> Self {
+        Self::Disconnected(message)
+    }
+}
+
+impl From<RecvError> for TryRecvError {
+    fn from(_: RecvError) -> Self {
+        Self::Disconnected
+    }
+}
+
+impl From<RecvError> for RecvTimeoutError {
+    fn from(_: RecvError) -> Self {
+        Self::Disconnected
+    }
+}
+
+// the messages don't have to be Debug, so their errors aren't either. like std's channel errors
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SendError").finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Debug for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Full(_) => f.write_str("Full(..)"),
+            Self::Disconnected(_) => f.write_str("Disconnected(..)"),
+        }
+    }
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("sending on a disconnected channel")
+    }
+}
+
+impl<T> fmt::Display for TrySendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Full(_) => f.write_str("sending on a full channel"),
+            Self::Disconnected(_) => f.write_str("sending on a disconnected channel"),
+        }
+    }
+}
+
+impl fmt::Display for RecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("receiving on an empty and disconnected channel")
+    }
+}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => f.write_str("receiving on an empty channel"),
+            Self::Disconnected => f.write_str("receiving on an empty and disconnected channel"),
+        }
+    }
+}
+
+impl fmt::Display for RecvTimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Timeout => f.write_str("timed out waiting for a message"),
+            Self::Disconnected => f.write_str("receiving on an empty and disconnected channel"),
+        }
+    }
+}
+
+impl<T> Error for SendError<T> {}
+impl<T> Error for TrySendError<T> {}
+impl Error for RecvError {}
+impl Error for TryRecvError {}
+impl Error for RecvTimeoutError {}
+
+#[test]
+fn channel_errors() {
+    // every channel reports its failures with the same types
+    fn send_and_receive() -> Result<(), Box<dyn Error>> {
+        let (sender, receiver) = super::simple_channel();
+        sender.send(1)?;
+        drop(sender);
+        assert_eq!(receiver.recv()?, 1);
+        receiver.recv()?;
+        Ok(())
+    }
+    let error = send_and_receive().unwrap_err();
+    assert_eq!(error.downcast_ref(), Some(&RecvError));
+    assert_eq!(
+        error.to_string(),
+        "receiving on an empty and disconnected channel"
+    );
+
+    let (sender, receiver) = super::mpmc::channel(1);
+    drop(receiver);
+    let error = sender.send(String::from("unsent")).unwrap_err();
+    assert_eq!(
+        format!("{error} {error:?}"),
+        "sending on a disconnected channel SendError { .. }"
+    );
+    assert_eq!(error.into_inner(), "unsent");
+
+    let (sender, receiver) = super::channel::<()>();
+    drop(sender);
+    assert_eq!(receiver.try_recv(), Err(TryRecvError::Disconnected));
+    assert_eq!(
+        TrySendError::from(SendError(2)),
+        TrySendError::Disconnected(2)
+    );
+    assert_eq!(
+        RecvTimeoutError::from(RecvError).to_string(),
+        RecvError.to_string()
+    );
+}
@@ -0,0 +1,552 @@
+//! A bounded multi-producer multi-consumer channel built on our own [Mutex] and [Condvar] from [crate::ch9].
+//! - [Sender] and [Receiver] can both be cloned and shared between threads.
+//! - [Sender::send] blocks while the channel is full, [Receiver::recv] blocks while it is empty.
+//! - When every [Receiver] is gone sending fails, and when every [Sender] is gone receiving fails
+//!   (after the messages that are still in the channel have been received).
+//! - [Receiver::recv_async] returns a [Future], for receiving from async code without blocking the thread.
+
+use super::*;
+
+pub use super::error::{RecvError, RecvTimeoutError, SendError, TryRecvError, TrySendError};
+
+struct State<T> {
+    messages: VecDeque<T>,
+    capacity: usize,
+    sender_count: usize,
+    receiver_count: usize,
+    /// Tasks awaiting [Receiver::recv_async], keyed by the [RecvFuture] that registered them.
+    /// The oldest one is woken (and removed) when a message is added, all of them when the last [Sender] is dropped.
+    receive_wakers: VecDeque<(u64, Waker)>,
+    next_waker_key: u64,
+}
+
+impl<T> State<T> {
+    /// Takes the waker of the task that has waited longest, to wake it after the lock is released.
+    fn take_receive_waker(&mut self) -> Option<Waker> {
+        self.receive_wakers.pop_front().map(|(_, waker)| waker)
+    }
+
+    /// Takes every registered waker, to wake them after the lock is released.
+    fn take_receive_wakers(&mut self) -> Vec<Waker> {
+        self.receive_wakers
+            .drain(..)
+            .map(|(_, waker)| waker)
+            .collect()
+    }
+
+    /// Returns whether the waker registered under `key` was still waiting to be woken.
+    fn unregister_receive_waker(&mut self, key: u64) -> bool {
+        let len = self.receive_wakers.len();
+        self.receive_wakers.retain(|(k, _)| *k != key);
+        self.receive_wakers.len() != len
+    }
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    /// Notified when a message is added, or when the last [Sender] is dropped.
+    not_empty: Condvar,
+    /// Notified when a message is removed, or when the last [Receiver] is dropped.
+    not_full: Condvar,
+}
+
+/// Creates a channel that holds at most `capacity` messages.
+/// # Panics
+/// - When `capacity == 0`
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(
+        capacity > 0,
+        "a bounded channel needs room for at least one message"
+    );
+
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            messages: VecDeque::with_capacity(capacity),
+            capacity,
+            sender_count: 1,
+            receiver_count: 1,
+            receive_wakers: VecDeque::new(),
+            next_waker_key: 0,
+        }),
+        not_empty: Condvar::new(),
+        not_full: Condvar::new(),
+    });
+
+    let sender = Sender {
+        shared: Arc::clone(&shared),
+    };
+    let receiver = Receiver { shared };
+
+    (sender, receiver)
+}
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Blocks while the channel is full.
+    /// # Errors
+    /// - When every [Receiver] was dropped
+    pub fn send(&self, message: T) -> Result<(), SendError<T>> {
+        let state = self.shared.state.lock();
+
+        // wait for room in the queue
+        let mut state = self
+            .shared
+            .not_full
+            .wait_while(state, |state| {
+                state.messages.len() == state.capacity && state.receiver_count > 0
+            })
+            .unwrap_or_else(PoisonError::into_inner);
+
+        if state.receiver_count == 0 {
+            return Err(SendError(message));
+        }
+
+        state.messages.push_back(message);
+        let waker = state.take_receive_waker();
+        drop(state);
+
+        // Notify a blocked receiver (and a waiting task) that a message is ready
+        self.shared.not_empty.notify_one();
+        waker.into_iter().for_each(Waker::wake);
+
+        Ok(())
+    }
+
+    /// # Errors
+    /// - [TrySendError::Full] instead of waiting for room
+    /// - [TrySendError::Disconnected] when every [Receiver] was dropped
+    pub fn try_send(&self, message: T) -> Result<(), TrySendError<T>> {
+        let mut state = self.shared.state.lock();
+
+        if state.receiver_count == 0 {
+            return Err(TrySendError::Disconnected(message));
+        }
+        if state.messages.len() == state.capacity {
+            return Err(TrySendError::Full(message));
+        }
+
+        state.messages.push_back(message);
+        let waker = state.take_receive_waker();
+        drop(state);
+
+        self.shared.not_empty.notify_one();
+        waker.into_iter().for_each(Waker::wake);
+
+        Ok(())
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Blocks while the channel is empty.
+    /// # Errors
+    /// - When every [Sender] was dropped and the channel is empty
+    pub fn recv(&self) -> Result<T, RecvError> {
+        let state = self.shared.state.lock();
+
+        // wait for a message
+        let mut state = self
+            .shared
+            .not_empty
+            .wait_while(state, |state| {
+                state.messages.is_empty() && state.sender_count > 0
+            })
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let message = state.messages.pop_front().ok_or(RecvError)?;
+        drop(state);
+
+        // Notify a blocked sender that there is room
+        self.shared.not_full.notify_one();
+
+        Ok(message)
+    }
+
+    /// # Errors
+    /// - [TryRecvError::Empty] instead of waiting for a message
+    /// - [TryRecvError::Disconnected] when every [Sender] was dropped and the channel is empty
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        let mut state = self.shared.state.lock();
+
+        let message = match state.messages.pop_front() {
+            Some(message) => message,
+            None if state.sender_count == 0 => return Err(TryRecvError::Disconnected),
+            None => return Err(TryRecvError::Empty),
+        };
+        drop(state);
+
+        self.shared.not_full.notify_one();
+
+        Ok(message)
+    }
+
+    /// Like [Receiver::recv] but gives up after `timeout`.
+    /// # Errors
+    /// - [RecvTimeoutError::Timeout] when no message arrived in time
+    /// - [RecvTimeoutError::Disconnected] when every [Sender] was dropped and the channel is empty
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let Some(deadline) = Instant::now().checked_add(timeout) else {
+            // a deadline too far away to represent is never reached
+            return self.recv().map_err(RecvTimeoutError::from);
+        };
+        let mut state = self.shared.state.lock();
+
+        let message = loop {
+            match state.messages.pop_front() {
+                Some(message) => break message,
+                None if state.sender_count == 0 => return Err(RecvTimeoutError::Disconnected),
+                None => {}
+            }
+
+            // Condvar::wait_timeout can return early, so keep track of the time that is left
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            (state, _) = self
+                .shared
+                .not_empty
+                .wait_timeout(state, remaining)
+                .unwrap_or_else(PoisonError::into_inner);
+        };
+        drop(state);
+
+        self.shared.not_full.notify_one();
+
+        Ok(message)
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Returns a future that resolves like [Receiver::recv], but waits without blocking the thread.
+    pub fn recv_async(&self) -> RecvFuture<'_, T> {
+        RecvFuture {
+            receiver: self,
+            key: None,
+        }
+    }
+}
+
+/// The [Future] returned by [Receiver::recv_async].
+pub struct RecvFuture<'a, T> {
+    receiver: &'a Receiver<T>,
+    /// The key of this future's entry in `receive_wakers`, once it has been polled.
+    key: Option<u64>,
+}
+
+impl<T> Future for RecvFuture<'_, T> {
+    type Output = Result<T, RecvError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let shared = &this.receiver.shared;
+        let mut state = shared.state.lock();
+
+        let message = match state.messages.pop_front() {
+            Some(message) => message,
+            None if state.sender_count == 0 => return Poll::Ready(Err(RecvError)),
+            None => {
+                // checked and registered while holding the lock, so a sender can't add a message in between.
+                // a future polled again keeps a single entry
+                let registered = this
+                    .key
+                    .and_then(|key| state.receive_wakers.iter_mut().find(|(k, _)| *k == key));
+                if let Some((_, waker)) = registered {
+                    waker.clone_from(cx.waker());
+                } else {
+                    let key = state.next_waker_key;
+                    state.next_waker_key += 1;
+                    state.receive_wakers.push_back((key, cx.waker().clone()));
+                    this.key = Some(key);
+                }
+                return Poll::Pending;
+            }
+        };
+        if let Some(key) = this.key.take() {
+            state.unregister_receive_waker(key);
+        }
+        drop(state);
+
+        shared.not_full.notify_one();
+
+        Poll::Ready(Ok(message))
+    }
+}
+
+impl<T> Drop for RecvFuture<'_, T> {
+    fn drop(&mut self) {
+        let Some(key) = self.key else {
+            return;
+        };
+        let mut state = self.receiver.shared.state.lock();
+
+        // woken for a message, but dropped before taking it: pass the wake-up on to the next task
+        let is_woken = !state.unregister_receive_waker(key);
+        let waker = if is_woken && !state.messages.is_empty() {
+            state.take_receive_waker()
+        } else {
+            None
+        };
+        drop(state);
+
+        waker.into_iter().for_each(Waker::wake);
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().sender_count += 1;
+        Self {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().receiver_count += 1;
+        Self {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock();
+        state.sender_count -= 1;
+        let is_last = state.sender_count == 0;
+        let wakers = if is_last {
+            state.take_receive_wakers()
+        } else {
+            Vec::new()
+        };
+        drop(state);
+
+        // receivers waiting for a message have to find out there won't be any
+        if is_last {
+            self.shared.not_empty.notify_all();
+            wakers.into_iter().for_each(Waker::wake);
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock();
+        state.receiver_count -= 1;
+        let is_last = state.receiver_count == 0;
+        drop(state);
+
+        // senders waiting for room have to find out nobody will receive
+        if is_last {
+            self.shared.not_full.notify_all();
+        }
+    }
+}
+
+#[test]
+fn mpmc_channel() {
+    let (sender, receiver) = channel(4);
+    let producer_count = 4;
+    let consumer_count = 4;
+    let messages_per_producer = 1000;
+
+    let received = thread::scope(|s| {
+        for producer in 0..producer_count {
+            let sender = sender.clone();
+            s.spawn(move || {
+                for i in 0..messages_per_producer {
+                    sender.send(producer * messages_per_producer + i).unwrap();
+                }
+            });
+        }
+        // only the clones keep the channel connected
+        drop(sender);
+
+        let consumers: Vec<_> = (0..consumer_count)
+            .map(|_| {
+                let receiver = receiver.clone();
+                s.spawn(move || {
+                    let mut received = Vec::new();
+                    while let Ok(message) = receiver.recv() {
+                        received.push(message);
+                    }
+                    received
+                })
+            })
+            .collect();
+
+        consumers
+            .into_iter()
+            .flat_map(|consumer| consumer.join().unwrap())
+            .collect::<Vec<_>>()
+    });
+
+    // every message arrived exactly once
+    let mut received = received;
+    received.sort();
+    assert_eq!(
+        received,
+        (0..producer_count * messages_per_producer).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn mpmc_send_blocks_when_full() {
+    let (sender, receiver) = channel(2);
+
+    sender.send(1).unwrap();
+    sender.try_send(2).unwrap();
+    assert_eq!(sender.try_send(3), Err(TrySendError::Full(3)));
+
+    thread::scope(|s| {
+        let blocked_sender = s.spawn(|| sender.send(3));
+
+        thread::sleep(Duration::from_millis(50));
+        assert!(!blocked_sender.is_finished());
+
+        // make room
+        assert_eq!(receiver.recv(), Ok(1));
+        blocked_sender.join().unwrap().unwrap();
+    });
+
+    assert_eq!(receiver.try_recv(), Ok(2));
+    assert_eq!(receiver.try_recv(), Ok(3));
+    assert_eq!(receiver.try_recv(), Err(TryRecvError::Empty));
+}
+
+#[test]
+fn mpmc_disconnect() {
+    // receivers drain the channel before reporting the disconnect
+    let (sender, receiver) = channel(2);
+    let sender_2 = sender.clone();
+    sender.send("a").unwrap();
+    drop(sender);
+    sender_2.send("b").unwrap();
+    drop(sender_2);
+    assert_eq!(receiver.recv(), Ok("a"));
+    assert_eq!(receiver.recv_timeout(Duration::ZERO), Ok("b"));
+    assert_eq!(receiver.recv(), Err(RecvError));
+    assert_eq!(receiver.try_recv(), Err(TryRecvError::Disconnected));
+
+    // the message comes back when nobody can receive it
+    let (sender, receiver) = channel(1);
+    drop(receiver);
+    assert_eq!(sender.send("c"), Err(SendError("c")));
+    assert_eq!(sender.try_send("d"), Err(TrySendError::Disconnected("d")));
+
+    // blocked threads are woken up by the disconnect
+    let (sender, receiver) = channel::<()>(1);
+    thread::scope(|s| {
+        let blocked_receiver = s.spawn(move || receiver.recv());
+        thread::sleep(Duration::from_millis(50));
+        drop(sender);
+        assert_eq!(blocked_receiver.join().unwrap(), Err(RecvError));
+    });
+
+    let (sender, receiver) = channel(1);
+    sender.send(1).unwrap();
+    thread::scope(|s| {
+        let blocked_sender = s.spawn(move || sender.send(2));
+        thread::sleep(Duration::from_millis(50));
+        drop(receiver);
+        assert_eq!(blocked_sender.join().unwrap(), Err(SendError(2)));
+    });
+}
+
+#[test]
+fn mpmc_recv_timeout() {
+    let (sender, receiver) = channel(1);
+
+    let start = Instant::now();
+    assert_eq!(
+        receiver.recv_timeout(Duration::from_millis(50)),
+        Err(RecvTimeoutError::Timeout)
+    );
+    assert!(start.elapsed() >= Duration::from_millis(50));
+
+    thread::scope(|s| {
+        s.spawn(|| {
+            thread::sleep(Duration::from_millis(10));
+            sender.send(1).unwrap();
+        });
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(10)), Ok(1));
+    });
+
+    // too long to add to Instant::now(), waits without a deadline
+    sender.send(2).unwrap();
+    assert_eq!(receiver.recv_timeout(Duration::MAX), Ok(2));
+
+    drop(sender);
+    assert_eq!(
+        receiver.recv_timeout(Duration::MAX),
+        Err(RecvTimeoutError::Disconnected)
+    );
+}
+
+#[test]
+fn mpmc_recv_async() {
+    let (sender, receiver) = channel(1);
+
+    thread::scope(|s| {
+        s.spawn(|| {
+            for i in 0..10 {
+                sender.send(i).unwrap();
+            }
+            drop(sender);
+        });
+
+        let received = block_on(async {
+            let mut received = Vec::new();
+            while let Ok(message) = receiver.recv_async().await {
+                received.push(message);
+            }
+            received
+        });
+        assert_eq!(received, (0..10).collect::<Vec<_>>());
+    });
+}
+
+#[test]
+fn mpmc_recv_async_wakes_one_task_per_message() {
+    use std::task::Wake;
+
+    struct CountWakes(AtomicUsize);
+    impl Wake for CountWakes {
+        fn wake(self: std::sync::Arc<Self>) {
+            self.0.fetch_add(1, Relaxed);
+        }
+    }
+
+    let (sender, receiver) = channel(2);
+    let wakes = [(); 3].map(|_| std::sync::Arc::new(CountWakes(AtomicUsize::new(0))));
+    let wake_counts = || wakes.each_ref().map(|wakes| wakes.0.load(Relaxed));
+    let wakers = wakes.each_ref().map(|wakes| Waker::from(wakes.clone()));
+    let mut futures = [(); 3].map(|_| Box::pin(receiver.recv_async()));
+    for (future, waker) in futures.iter_mut().zip(&wakers) {
+        let poll = future.as_mut().poll(&mut Context::from_waker(waker));
+        assert_eq!(poll, Poll::Pending);
+    }
+
+    sender.send(1).unwrap();
+    assert_eq!(wake_counts(), [1, 0, 0]);
+
+    // the woken future is dropped without taking the message, so the next one is woken instead
+    let [first, mut second, third] = futures;
+    drop(first);
+    assert_eq!(wake_counts(), [1, 1, 0]);
+    let poll = second.as_mut().poll(&mut Context::from_waker(&wakers[1]));
+    assert_eq!(poll, Poll::Ready(Ok(1)));
+
+    // a dropped pending future doesn't stay registered
+    drop(third);
+    assert!(receiver.shared.state.lock().receive_wakers.is_empty());
+    sender.send(2).unwrap();
+    assert_eq!(wake_counts(), [1, 1, 0]);
+}
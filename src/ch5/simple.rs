@@ -0,0 +1,277 @@
+//! The simple channel from the book: a [VecDeque] behind our own [Mutex], and a [Condvar] for the receivers to wait on.
+//! - [SimpleChannel] is shared by reference, or [SimpleChannel::split] into [Sender] and [Receiver] handles.
+//! - Messages are received in the order they were sent (first in, first out).
+//! - A closed channel doesn't accept messages, and receiving from it fails once it is empty.
+//!   [SimpleChannel::close] closes it, and so does dropping every [Sender] or every [Receiver].
+//! - The channel is unbounded: sending never blocks.
+
+use super::*;
+
+pub use super::error::{RecvError, SendError};
+
+struct State<T> {
+    messages: VecDeque<T>,
+    is_closed: bool,
+    /// Only counted after [SimpleChannel::split].
+    sender_count: usize,
+    receiver_count: usize,
+}
+
+/// A channel built on our own [Mutex] and [Condvar] from [crate::ch9].
+pub struct SimpleChannel<T> {
+    state: Mutex<State<T>>,
+    /// Notified when a message is added, or when the channel is closed.
+    message_ready: Condvar,
+}
+
+impl<T> Default for SimpleChannel<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<T> SimpleChannel<T> {
+    pub const fn new() -> Self {
+        Self {
+            state: Mutex::new(State {
+                messages: VecDeque::new(),
+                is_closed: false,
+                sender_count: 0,
+                receiver_count: 0,
+            }),
+            message_ready: Condvar::new(),
+        }
+    }
+
+    /// Adds the message to the back of the queue.
+    /// # Errors
+    /// - When the channel is closed
+    pub fn send(&self, message: T) -> Result<(), SendError<T>> {
+        let mut state = self.state.lock();
+        if state.is_closed {
+            return Err(SendError(message));
+        }
+
+        // add the message to the queue
+        state.messages.push_back(message);
+        drop(state);
+
+        // Notify a blocked thread that a message is ready
+        self.message_ready.notify_one();
+
+        Ok(())
+    }
+
+    /// Takes the message from the front of the queue, the oldest one. Blocks while the channel is empty.
+    /// # Errors
+    /// - When the channel is closed and empty
+    pub fn receive(&self) -> Result<T, RecvError> {
+        let state = self.state.lock();
+
+        // wait for a message, or for the channel to close
+        let mut state = self
+            .message_ready
+            .wait_while(state, |state| state.messages.is_empty() && !state.is_closed)
+            .unwrap_or_else(PoisonError::into_inner);
+
+        state.messages.pop_front().ok_or(RecvError)
+    }
+
+    /// Stops accepting messages. The messages already in the channel can still be received.
+    pub fn close(&self) {
+        self.state.lock().is_closed = true;
+
+        // receivers waiting for a message have to find out there won't be any
+        self.message_ready.notify_all();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.state.lock().is_closed
+    }
+
+    /// Moves the channel into a [Sender] and [Receiver] pair, which can both be cloned.
+    pub fn split(self) -> (Sender<T>, Receiver<T>) {
+        {
+            let mut state = self.state.lock();
+            state.sender_count = 1;
+            state.receiver_count = 1;
+        }
+
+        let channel = Arc::new(self);
+        let sender = Sender {
+            channel: Arc::clone(&channel),
+        };
+        let receiver = Receiver { channel };
+
+        (sender, receiver)
+    }
+}
+
+/// Creates a [SimpleChannel] and [SimpleChannel::split]s it.
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    SimpleChannel::new().split()
+}
+
+pub struct Sender<T> {
+    channel: Arc<SimpleChannel<T>>,
+}
+pub struct Receiver<T> {
+    channel: Arc<SimpleChannel<T>>,
+}
+
+impl<T> Sender<T> {
+    /// # Errors
+    /// - When the channel is closed, which it is when every [Receiver] was dropped
+    pub fn send(&self, message: T) -> Result<(), SendError<T>> {
+        self.channel.send(message)
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Blocks while the channel is empty.
+    /// # Errors
+    /// - When the channel is closed and empty, which it is when every [Sender] was dropped
+    pub fn recv(&self) -> Result<T, RecvError> {
+        self.channel.receive()
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.channel.state.lock().sender_count += 1;
+        Self {
+            channel: Arc::clone(&self.channel),
+        }
+    }
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        self.channel.state.lock().receiver_count += 1;
+        Self {
+            channel: Arc::clone(&self.channel),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.channel.state.lock();
+        state.sender_count -= 1;
+        let is_last = state.sender_count == 0;
+        drop(state);
+
+        if is_last {
+            self.channel.close();
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.channel.state.lock();
+        state.receiver_count -= 1;
+        let is_last = state.receiver_count == 0;
+        drop(state);
+
+        // nobody is left to receive, so sending fails from now on
+        if is_last {
+            self.channel.close();
+        }
+    }
+}
+
+#[test]
+fn simple_channel_is_fifo() {
+    let simple = SimpleChannel::new();
+    for message in 0..10 {
+        simple.send(message).unwrap();
+    }
+    for message in 0..10 {
+        assert_eq!(simple.receive(), Ok(message));
+    }
+
+    // also between threads: a single sender's messages arrive in order
+    let (sender, receiver) = channel();
+    thread::scope(|s| {
+        s.spawn(move || (0..1000).for_each(|message| sender.send(message).unwrap()));
+        for message in 0..1000 {
+            assert_eq!(receiver.recv(), Ok(message));
+        }
+    });
+}
+
+#[test]
+fn simple_channel_shared_by_reference() {
+    let simple = SimpleChannel::new();
+    let thread_count = 4;
+    let messages_per_thread = 1000;
+
+    let received = thread::scope(|s| {
+        for thread in 0..thread_count {
+            let simple = &simple;
+            s.spawn(move || {
+                for i in 0..messages_per_thread {
+                    simple.send(thread * messages_per_thread + i).unwrap();
+                }
+            });
+        }
+        let received: Vec<_> = (0..thread_count * messages_per_thread)
+            .map(|_| simple.receive().unwrap())
+            .collect();
+        received
+    });
+
+    // every message arrived exactly once
+    let mut received = received;
+    received.sort();
+    assert_eq!(
+        received,
+        (0..thread_count * messages_per_thread).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn simple_channel_close() {
+    // receivers drain the channel before reporting that it is closed
+    let simple = SimpleChannel::new();
+    simple.send("a").unwrap();
+    simple.close();
+    assert!(simple.is_closed());
+    assert_eq!(simple.send("b"), Err(SendError("b")));
+    assert_eq!(simple.receive(), Ok("a"));
+    assert_eq!(simple.receive(), Err(RecvError));
+
+    // dropping every sender closes the channel
+    let (sender, receiver) = channel();
+    let sender_2 = sender.clone();
+    sender.send(1).unwrap();
+    drop(sender);
+    sender_2.send(2).unwrap();
+    drop(sender_2);
+    assert_eq!(receiver.recv(), Ok(1));
+    assert_eq!(receiver.recv(), Ok(2));
+    assert_eq!(receiver.recv(), Err(RecvError));
+
+    // and so does dropping every receiver
+    let (sender, receiver) = channel();
+    drop(receiver.clone());
+    sender.send(1).unwrap();
+    drop(receiver);
+    assert_eq!(sender.send(2), Err(SendError(2)));
+
+    // blocked receivers are woken up when the last sender is dropped
+    let (sender, receiver) = channel::<()>();
+    thread::scope(|s| {
+        let blocked_receivers: Vec<_> = (0..2)
+            .map(|_| {
+                let receiver = receiver.clone();
+                s.spawn(move || receiver.recv())
+            })
+            .collect();
+        thread::sleep(Duration::from_millis(50));
+        drop(sender);
+        for blocked_receiver in blocked_receivers {
+            assert_eq!(blocked_receiver.join().unwrap(), Err(RecvError));
+        }
+    });
+}
@@ -0,0 +1,458 @@
+//! A bounded single-producer single-consumer channel: a ring buffer without a lock.
+//! - [channel()] returns the only [Producer] and the only [Consumer]. Neither can be cloned, and both methods take `&mut self`.
+//! - The slots are [MaybeUninit], like the message of the oneshot channels' `MessageSlot`: the slots from `head` up to `tail` are initialized.
+//! - Only the [Producer] moves `tail` and only the [Consumer] moves `head`, each on its own cache line.
+//!   Both keep a copy of the other one's index, and only load the real one when the copy says the buffer is full (or empty).
+//! - [Producer::push_slice] and [Consumer::pop_into] move a whole batch with a single index update.
+//! - [Producer::push] and [Consumer::pop] block (park the thread) while the buffer is full or empty.
+//!   A push or pop only wakes the other side when it is waiting, so the fast path never does a read-modify-write.
+
+use super::*;
+use crate::cache_padded::CachePadded;
+
+struct Buffer<T> {
+    /// `slots.len()` is a power of two, so a position maps to its slot with `position & mask`.
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    mask: usize,
+    /// The position of the next message to pop. Only the [Consumer] changes it.
+    /// Positions wrap around at `usize::MAX`, `tail - head` is still the number of messages.
+    head: CachePadded<AtomicUsize>,
+    /// The [Producer] waiting for room, woken up by a pop and when the [Consumer] is dropped.
+    producer_waiter: CachePadded<Waiter>,
+    /// The position of the next slot to push into. Only the [Producer] changes it.
+    tail: CachePadded<AtomicUsize>,
+    /// The [Consumer] waiting for a message, woken up by a push and when the [Producer] is dropped.
+    consumer_waiter: CachePadded<Waiter>,
+    is_producer_dropped: AtomicBool,
+    is_consumer_dropped: AtomicBool,
+}
+
+// SAFETY: a slot is only accessed by the Producer while it is outside head..tail, and by the Consumer while it is inside
+unsafe impl<T: Send> Sync for Buffer<T> {}
+
+impl<T> Buffer<T> {
+    fn slot(&self, position: usize) -> *mut MaybeUninit<T> {
+        self.slots[position & self.mask].get()
+    }
+}
+
+/// The [AtomicWaker] of a blocked [Producer] or [Consumer], and whether it is waiting.
+/// `is_waiting` lets the other side skip the read-modify-write of [AtomicWaker::wake] on every push or pop.
+struct Waiter {
+    is_waiting: AtomicBool,
+    waker: AtomicWaker,
+}
+
+impl Waiter {
+    const fn new() -> Self {
+        Self {
+            is_waiting: AtomicBool::new(false),
+            waker: AtomicWaker::new(),
+        }
+    }
+
+    /// Registers `waker`. Check the index again afterwards, and park if it didn't move.
+    fn register(&self, waker: &Waker) {
+        self.waker.register(waker);
+        self.is_waiting.store(true, Relaxed);
+        // pairs with the fence in Waiter::wake_if_waiting: either our check sees the index moved,
+        // or the other side sees is_waiting
+        fence(SeqCst);
+    }
+
+    /// Done waiting, the other side can stop waking us.
+    fn unregister(&self) {
+        self.is_waiting.store(false, Relaxed);
+    }
+
+    /// Call after moving the index the waiting side checks.
+    fn wake_if_waiting(&self) {
+        // pairs with the fence in Waiter::register
+        fence(SeqCst);
+        if self.is_waiting.load(Relaxed) {
+            self.waker.wake();
+        }
+    }
+}
+
+impl<T> Drop for Buffer<T> {
+    fn drop(&mut self) {
+        // both halves are gone, drop the messages nobody popped
+        let tail = *self.tail.get_mut();
+        let mut head = *self.head.get_mut();
+        while head != tail {
+            // SAFETY: the slots from head up to tail are initialized
+            unsafe { (*self.slot(head)).assume_init_drop() };
+            head = head.wrapping_add(1);
+        }
+    }
+}
+
+/// Creates a channel that holds at least `capacity` messages. `capacity` is rounded up to a power of two.
+/// # Panics
+/// - When `capacity == 0`, or when rounding it up overflows
+pub fn channel<T>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    assert!(
+        capacity > 0,
+        "a ring buffer needs room for at least one message"
+    );
+    let capacity = capacity
+        .checked_next_power_of_two()
+        .expect("capacity is too large");
+
+    let buffer = Arc::new(Buffer {
+        slots: (0..capacity)
+            .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+            .collect(),
+        mask: capacity - 1,
+        head: CachePadded(AtomicUsize::new(0)),
+        producer_waiter: CachePadded(Waiter::new()),
+        tail: CachePadded(AtomicUsize::new(0)),
+        consumer_waiter: CachePadded(Waiter::new()),
+        is_producer_dropped: AtomicBool::new(false),
+        is_consumer_dropped: AtomicBool::new(false),
+    });
+
+    let producer = Producer {
+        buffer: Arc::clone(&buffer),
+        tail: 0,
+        cached_head: 0,
+    };
+    let consumer = Consumer {
+        buffer,
+        head: 0,
+        cached_tail: 0,
+    };
+
+    (producer, consumer)
+}
+
+pub struct Producer<T> {
+    buffer: Arc<Buffer<T>>,
+    /// Our own copy of `buffer.tail`, nobody else changes it.
+    tail: usize,
+    /// The last `buffer.head` we loaded. The real one is never behind it.
+    cached_head: usize,
+}
+
+pub struct Consumer<T> {
+    buffer: Arc<Buffer<T>>,
+    /// Our own copy of `buffer.head`, nobody else changes it.
+    head: usize,
+    /// The last `buffer.tail` we loaded. The real one is never behind it.
+    cached_tail: usize,
+}
+
+impl<T> Producer<T> {
+    /// The number of messages the buffer holds.
+    pub fn capacity(&self) -> usize {
+        self.buffer.slots.len()
+    }
+
+    /// Returns `true` when the [Consumer] was dropped, so messages would never be popped.
+    pub fn is_closed(&self) -> bool {
+        self.buffer.is_consumer_dropped.load(Relaxed)
+    }
+
+    /// The number of empty slots, at least `wanted` when possible.
+    /// Only loads `head` when the cached one says there are fewer than `wanted`.
+    fn free_slots(&mut self, wanted: usize) -> usize {
+        let free = self.capacity() - self.tail.wrapping_sub(self.cached_head);
+        if free >= wanted {
+            return free;
+        }
+        // Acquire: the consumer is done reading the slots before head
+        self.cached_head = self.buffer.head.load(Acquire);
+        self.capacity() - self.tail.wrapping_sub(self.cached_head)
+    }
+
+    /// Publishes the slots up to `tail` and wakes the [Consumer] if it is waiting.
+    fn publish(&mut self, tail: usize) {
+        self.tail = tail;
+        // Release: the consumer sees the messages in the slots before tail
+        self.buffer.tail.store(tail, Release);
+        self.buffer.consumer_waiter.wake_if_waiting();
+    }
+
+    /// # Errors
+    /// - [TrySendError::Full] instead of waiting for room
+    /// - [TrySendError::Disconnected] when the [Consumer] was dropped
+    pub fn try_push(&mut self, message: T) -> Result<(), TrySendError<T>> {
+        if self.is_closed() {
+            return Err(TrySendError::Disconnected(message));
+        }
+        if self.free_slots(1) == 0 {
+            return Err(TrySendError::Full(message));
+        }
+
+        // SAFETY: the slot at tail is empty, and the consumer doesn't look at it until tail moves past it
+        unsafe { (*self.buffer.slot(self.tail)).write(message) };
+        self.publish(self.tail.wrapping_add(1));
+
+        Ok(())
+    }
+
+    /// Pushes as many messages from the front of `messages` as there is room for, and returns how many that was.
+    /// The [Consumer] sees the whole batch at once. Pushes nothing when the [Consumer] was dropped.
+    pub fn push_slice(&mut self, messages: &[T]) -> usize
+    where
+        T: Copy,
+    {
+        if self.is_closed() {
+            return 0;
+        }
+        let count = self.free_slots(messages.len()).min(messages.len());
+        if count == 0 {
+            return 0;
+        }
+
+        for (offset, &message) in messages[..count].iter().enumerate() {
+            // SAFETY: the count slots from tail are empty, see Producer::try_push
+            unsafe { (*self.buffer.slot(self.tail.wrapping_add(offset))).write(message) };
+        }
+        self.publish(self.tail.wrapping_add(count));
+
+        count
+    }
+
+    /// Blocks while the buffer is full.
+    /// # Errors
+    /// - When the [Consumer] was dropped
+    pub fn push(&mut self, message: T) -> Result<(), SendError<T>> {
+        let mut message = match self.try_push(message) {
+            Err(TrySendError::Full(message)) => message,
+            Err(TrySendError::Disconnected(message)) => return Err(SendError(message)),
+            Ok(()) => return Ok(()),
+        };
+
+        let waker = thread_waker();
+        loop {
+            // register before checking, so a pop that happens after the check will unpark us
+            self.buffer.producer_waiter.register(&waker);
+
+            message = match self.try_push(message) {
+                Err(TrySendError::Full(message)) => message,
+                result => {
+                    self.buffer.producer_waiter.unregister();
+                    return result.map_err(|error| SendError(error.into_inner()));
+                }
+            };
+            thread::park();
+        }
+    }
+}
+
+impl<T> Consumer<T> {
+    /// The number of messages the buffer holds.
+    pub fn capacity(&self) -> usize {
+        self.buffer.slots.len()
+    }
+
+    /// Returns `true` when the [Producer] was dropped. There still can be messages to pop.
+    pub fn is_closed(&self) -> bool {
+        self.buffer.is_producer_dropped.load(Relaxed)
+    }
+
+    /// The number of messages ready to pop, at least `wanted` when possible.
+    /// Only loads `tail` when the cached one says there are fewer than `wanted`.
+    fn ready_slots(&mut self, wanted: usize) -> usize {
+        let ready = self.cached_tail.wrapping_sub(self.head);
+        if ready >= wanted {
+            return ready;
+        }
+        // Acquire: the producer is done writing the slots before tail
+        self.cached_tail = self.buffer.tail.load(Acquire);
+        self.cached_tail.wrapping_sub(self.head)
+    }
+
+    /// Frees the slots before `head` and wakes the [Producer] if it is waiting.
+    fn release(&mut self, head: usize) {
+        self.head = head;
+        // Release: the producer only reuses the slots after we are done reading them
+        self.buffer.head.store(head, Release);
+        self.buffer.producer_waiter.wake_if_waiting();
+    }
+
+    /// # Errors
+    /// - [TryRecvError::Empty] instead of waiting for a message
+    /// - [TryRecvError::Disconnected] when the [Producer] was dropped and the buffer is empty
+    pub fn try_pop(&mut self) -> Result<T, TryRecvError> {
+        // check the flag before the buffer: the producer sets it after its last push,
+        // so if it is set and the buffer is empty, it stays empty
+        let is_producer_dropped = self.buffer.is_producer_dropped.load(Acquire);
+        if self.ready_slots(1) == 0 {
+            return match is_producer_dropped {
+                true => Err(TryRecvError::Disconnected),
+                false => Err(TryRecvError::Empty),
+            };
+        }
+
+        // SAFETY: the slot at head is initialized, and the producer doesn't reuse it until head moves past it
+        let message = unsafe { (*self.buffer.slot(self.head)).assume_init_read() };
+        self.release(self.head.wrapping_add(1));
+
+        Ok(message)
+    }
+
+    /// Pops as many messages as are ready into the front of `messages`, and returns how many that was.
+    /// The [Producer] gets the room back for the whole batch at once.
+    pub fn pop_into(&mut self, messages: &mut [T]) -> usize
+    where
+        T: Copy,
+    {
+        let count = self.ready_slots(messages.len()).min(messages.len());
+        if count == 0 {
+            return 0;
+        }
+
+        for (offset, message) in messages[..count].iter_mut().enumerate() {
+            // SAFETY: the count slots from head are initialized, see Consumer::try_pop
+            *message =
+                unsafe { (*self.buffer.slot(self.head.wrapping_add(offset))).assume_init_read() };
+        }
+        self.release(self.head.wrapping_add(count));
+
+        count
+    }
+
+    /// Blocks while the buffer is empty.
+    /// # Errors
+    /// - When the [Producer] was dropped and the buffer is empty
+    pub fn pop(&mut self) -> Result<T, RecvError> {
+        match self.try_pop() {
+            Err(TryRecvError::Empty) => {}
+            result => return result.map_err(|_| RecvError),
+        }
+
+        let waker = thread_waker();
+        loop {
+            // register before checking, so a push that happens after the check will unpark us
+            self.buffer.consumer_waiter.register(&waker);
+
+            match self.try_pop() {
+                Err(TryRecvError::Empty) => {}
+                result => {
+                    self.buffer.consumer_waiter.unregister();
+                    return result.map_err(|_| RecvError);
+                }
+            }
+            thread::park();
+        }
+    }
+}
+
+impl<T> Drop for Producer<T> {
+    fn drop(&mut self) {
+        // Release so a consumer that sees this flag also sees every message
+        self.buffer.is_producer_dropped.store(true, Release);
+        // a consumer blocked in Consumer::pop has to find out there won't be more messages
+        self.buffer.consumer_waiter.waker.wake();
+    }
+}
+
+impl<T> Drop for Consumer<T> {
+    fn drop(&mut self) {
+        self.buffer.is_consumer_dropped.store(true, Relaxed);
+        // a producer blocked in Producer::push has to find out nobody will pop
+        self.buffer.producer_waiter.waker.wake();
+    }
+}
+
+#[test]
+fn spsc_channel() {
+    let (mut producer, mut consumer) = channel(3);
+    assert_eq!(producer.capacity(), 4);
+
+    for message in 0..4 {
+        producer.try_push(message).unwrap();
+    }
+    assert_eq!(producer.try_push(4), Err(TrySendError::Full(4)));
+    assert_eq!(consumer.try_pop(), Ok(0));
+    producer.try_push(4).unwrap();
+
+    // the batch wraps around the end of the buffer
+    let mut popped = [0; 8];
+    assert_eq!(consumer.pop_into(&mut popped), 4);
+    assert_eq!(popped[..4], [1, 2, 3, 4]);
+    assert_eq!(producer.push_slice(&[5, 6, 7, 8, 9]), 4);
+    assert_eq!(consumer.pop_into(&mut popped[..2]), 2);
+    assert_eq!(popped[..2], [5, 6]);
+    assert_eq!(consumer.try_pop(), Ok(7));
+
+    // the consumer drains the buffer before reporting the disconnect
+    drop(producer);
+    assert!(consumer.is_closed());
+    assert_eq!(consumer.pop(), Ok(8));
+    assert_eq!(consumer.pop(), Err(RecvError));
+    assert_eq!(consumer.try_pop(), Err(TryRecvError::Disconnected));
+
+    let (mut producer, consumer) = channel(1);
+    drop(consumer);
+    assert_eq!(producer.push("unsent"), Err(SendError("unsent")));
+    assert_eq!(producer.push_slice(&["unsent"]), 0);
+}
+
+#[test]
+fn spsc_channel_between_threads() {
+    let (mut producer, mut consumer) = channel(16);
+    let message_count = 100_000;
+
+    thread::scope(|s| {
+        s.spawn(move || {
+            let messages: Vec<usize> = (0..message_count).collect();
+            for chunk in messages.chunks(7) {
+                // blocks on the first message of the chunk that doesn't fit, then batches the rest
+                producer.push(chunk[0]).unwrap();
+                let mut rest = &chunk[1..];
+                while !rest.is_empty() {
+                    rest = &rest[producer.push_slice(rest)..];
+                }
+            }
+        });
+
+        // alternate between blocking and batching, the messages arrive in order either way
+        let mut expected = 0;
+        let mut batch = [0; 5];
+        while let Ok(message) = consumer.pop() {
+            assert_eq!(message, expected);
+            expected += 1;
+            let count = consumer.pop_into(&mut batch);
+            assert_eq!(
+                batch[..count],
+                (expected..expected + count).collect::<Vec<_>>()
+            );
+            expected += count;
+        }
+        assert_eq!(expected, message_count);
+    });
+}
+
+#[test]
+fn spsc_channel_drop() {
+    // messages that were never popped are dropped with the buffer
+    let mut message = Arc::new(());
+    let (mut producer, mut consumer) = channel(4);
+    for _ in 0..3 {
+        producer.try_push(Arc::clone(&message)).unwrap();
+    }
+    drop(consumer.try_pop().unwrap());
+    drop((producer, consumer));
+    // only unique when every clone was dropped
+    assert!(Arc::get_mut(&mut message).is_some());
+}
+
+#[cfg(feature = "model")]
+#[test]
+fn model_spsc_channel() {
+    model::model(|| {
+        let (mut producer, mut consumer) = channel(1);
+        let thread = thread::spawn(move || {
+            producer.push(String::from("a")).unwrap();
+            producer.push(String::from("b")).unwrap();
+        });
+        assert_eq!(consumer.pop().as_deref(), Ok("a"));
+        assert_eq!(consumer.pop().as_deref(), Ok("b"));
+        assert_eq!(consumer.pop(), Err(RecvError));
+        thread.join().unwrap();
+    });
+}
@@ -0,0 +1,373 @@
+//! Building Our Own "Arc" Summary
+//! - Arc<T> provides shared ownership of a reference-counted allocation.
+//! - By checking if the reference counter is exactly one, an Arc<T> can conditionally provide exclusive access (&mut T).
+//! - Incrementing the atomic reference counter can be done using a relaxed operation,
+//!   but the final decrement must synchronize with all previous decrements.
+//! - A weak pointer (Weak<T>) can be used to avoid cycles.
+//! - The NonNull<T> type represents a pointer to T that is never null.
+//! - The ManuallyDrop<T> type can be used to manually decide, using unsafe code, when to drop a T.
+//! - As soon as more than one atomic variable is involved, things get more complicated.
+//! - Implementing an ad hoc (spin) lock can sometimes be a valid strategy for operating on multiple atomic variables at once.
+
+use std::{mem::ManuallyDrop, ops::Deref, ptr::NonNull};
+
+use super::*;
+
+/// The heap allocation shared by every [Arc] and [Weak] pointing at the same value.
+struct ArcData<T> {
+    /// Number of [Arc]s.
+    data_ref_count: AtomicUsize,
+    /// Number of [Weak]s, plus one if there are any [Arc]s.
+    /// [Arc::get_mut] temporarily sets this to `usize::MAX` to "lock" it.
+    alloc_ref_count: AtomicUsize,
+    /// The data. Dropped as soon as there are only [Weak]s left.
+    data: UnsafeCell<ManuallyDrop<T>>,
+}
+
+/// A thread-safe reference-counting pointer. Cloning an [Arc] only increments a counter;
+/// the `T` is dropped when the last [Arc] is dropped, and the allocation is freed when the last [Weak] is dropped too.
+pub struct Arc<T> {
+    ptr: NonNull<ArcData<T>>,
+}
+// An Arc<T> gives out &T to every thread holding a clone (T: Sync),
+// and whichever thread drops the last one also drops the T (T: Send).
+unsafe impl<T: Send + Sync> Send for Arc<T> {}
+unsafe impl<T: Send + Sync> Sync for Arc<T> {}
+
+/// A non-owning pointer to the allocation of an [Arc].
+/// A [Weak] does not keep the `T` alive, so it has to be [Weak::upgrade]d before the `T` can be accessed.
+pub struct Weak<T> {
+    ptr: NonNull<ArcData<T>>,
+}
+unsafe impl<T: Send + Sync> Send for Weak<T> {}
+unsafe impl<T: Send + Sync> Sync for Weak<T> {}
+
+impl<T> Arc<T> {
+    pub fn new(data: T) -> Self {
+        let data = Box::new(ArcData {
+            data_ref_count: AtomicUsize::new(1),
+            alloc_ref_count: AtomicUsize::new(1),
+            data: UnsafeCell::new(ManuallyDrop::new(data)),
+        });
+
+        Self {
+            ptr: NonNull::from(Box::leak(data)),
+        }
+    }
+
+    fn data(&self) -> &ArcData<T> {
+        // SAFETY: the allocation is only freed once every Arc and Weak is gone, and self is an Arc
+        unsafe { self.ptr.as_ref() }
+    }
+
+    /// Returns a mutable reference to the `T` only if this is the only [Arc] and there are no [Weak]s.
+    /// This is an associated function (`Arc::get_mut(&mut a)`) so it can't be confused with a method on `T`.
+    pub fn get_mut(arc: &mut Self) -> Option<&mut T> {
+        // "lock" the weak counter so no Weak can be created (by Arc::downgrade) while we check the data counter.
+        // Acquire matches Weak::drop's release-decrement, to make sure any upgraded pointers are visible in the next data_ref_count.load.
+        if arc
+            .data()
+            .alloc_ref_count
+            .compare_exchange(1, usize::MAX, Acquire, Relaxed)
+            .is_err()
+        {
+            return None;
+        }
+
+        let is_unique = arc.data().data_ref_count.load(Relaxed) == 1;
+
+        // "unlock" the weak counter.
+        // Release matches the acquire-increment in Arc::downgrade, to make sure any changes to
+        // the data_ref_count that come after downgrade don't change the is_unique result above.
+        arc.data().alloc_ref_count.store(1, Release);
+
+        if !is_unique {
+            return None;
+        }
+
+        // Acquire to match Arc::drop's release-decrement, to make sure nothing else is accessing the data.
+        fence(Acquire);
+
+        // SAFETY: there is exactly one Arc, which we have exclusive access to, and no Weaks.
+        unsafe { Some(&mut *arc.data().data.get()) }
+    }
+
+    /// Like [Arc::get_mut] except that when the `T` is shared it is cloned into a new allocation first (clone-on-write).
+    pub fn make_mut(arc: &mut Self) -> &mut T
+    where
+        T: Clone,
+    {
+        if Arc::get_mut(arc).is_none() {
+            // other Arcs or Weaks exist. They keep pointing at the old allocation
+            *arc = Arc::new(T::clone(arc));
+        }
+
+        Arc::get_mut(arc).expect("a freshly allocated Arc is always unique")
+    }
+
+    /// Creates a [Weak] pointing to the same allocation as `arc`.
+    pub fn downgrade(arc: &Self) -> Weak<T> {
+        let mut n = arc.data().alloc_ref_count.load(Relaxed);
+        loop {
+            // the weak counter is "locked" by Arc::get_mut
+            if n == usize::MAX {
+                hint::spin_loop();
+                n = arc.data().alloc_ref_count.load(Relaxed);
+                continue;
+            }
+
+            assert!(n < usize::MAX - 1);
+
+            // Acquire synchronizes with Arc::get_mut's release-store.
+            if let Err(e) =
+                arc.data()
+                    .alloc_ref_count
+                    .compare_exchange_weak(n, n + 1, Acquire, Relaxed)
+            {
+                n = e;
+                continue;
+            }
+
+            return Weak { ptr: arc.ptr };
+        }
+    }
+}
+
+impl<T> Deref for Arc<T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        // SAFETY: Since there's an Arc to the data, the data exists and may be shared.
+        unsafe { &*self.data().data.get_shared() }
+    }
+}
+
+impl<T> Clone for Arc<T> {
+    fn clone(&self) -> Self {
+        // Relaxed is enough: the new Arc is created from an existing one, which already keeps the data alive.
+        // abort instead of panicking because other threads might be cloning at the same time
+        if self.data().data_ref_count.fetch_add(1, Relaxed) > usize::MAX / 2 {
+            std::process::abort();
+        }
+
+        Self { ptr: self.ptr }
+    }
+}
+
+impl<T> Drop for Arc<T> {
+    fn drop(&mut self) {
+        // Release so every use of the data through this Arc happens-before the final decrement.
+        if self.data().data_ref_count.fetch_sub(1, Release) == 1 {
+            // this was the last Arc. acquire every previous release-decrement before dropping the data
+            fence(Acquire);
+
+            // SAFETY: The data reference counter is zero, so nothing will access the data anymore.
+            unsafe { ManuallyDrop::drop(&mut *self.data().data.get()) };
+
+            // Now that there's no Arc<T>s left, drop the implicit weak pointer that represented all Arc<T>s.
+            drop(Weak { ptr: self.ptr });
+        }
+    }
+}
+
+impl<T> Weak<T> {
+    fn data(&self) -> &ArcData<T> {
+        // SAFETY: the allocation is only freed once every Arc and Weak is gone, and self is a Weak
+        unsafe { self.ptr.as_ref() }
+    }
+
+    /// Returns an [Arc] to the data if it has not been dropped yet.
+    pub fn upgrade(&self) -> Option<Arc<T>> {
+        let mut n = self.data().data_ref_count.load(Relaxed);
+        loop {
+            // every Arc is gone, so the data was dropped
+            if n == 0 {
+                return None;
+            }
+
+            assert!(n < usize::MAX);
+
+            // never increment from zero, that would bring the data back from the dead
+            if let Err(e) =
+                self.data()
+                    .data_ref_count
+                    .compare_exchange_weak(n, n + 1, Relaxed, Relaxed)
+            {
+                n = e;
+                continue;
+            }
+
+            return Some(Arc { ptr: self.ptr });
+        }
+    }
+}
+
+impl<T> Clone for Weak<T> {
+    fn clone(&self) -> Self {
+        if self.data().alloc_ref_count.fetch_add(1, Relaxed) > usize::MAX / 2 {
+            std::process::abort();
+        }
+
+        Self { ptr: self.ptr }
+    }
+}
+
+impl<T> Drop for Weak<T> {
+    fn drop(&mut self) {
+        if self.data().alloc_ref_count.fetch_sub(1, Release) == 1 {
+            fence(Acquire);
+
+            // SAFETY: this was the last Weak (and there are no Arcs), so nothing can reach the allocation anymore.
+            unsafe { drop(Box::from_raw(self.ptr.as_ptr())) };
+        }
+    }
+}
+
+#[test]
+fn arc_drop() {
+    static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+
+    struct DetectDrop;
+    impl Drop for DetectDrop {
+        fn drop(&mut self) {
+            NUM_DROPS.fetch_add(1, Relaxed);
+        }
+    }
+
+    // Create two Arcs sharing an object containing a string and a DetectDrop, to detect when it's dropped.
+    let x = Arc::new(("hello", DetectDrop));
+    let y = x.clone();
+
+    // Send x to another thread, and use it there.
+    let t = thread::spawn(move || {
+        assert_eq!(x.0, "hello");
+    });
+
+    // In parallel, y should still be usable here.
+    assert_eq!(y.0, "hello");
+
+    // Wait for the thread to finish.
+    t.join().unwrap();
+
+    // One Arc, x, should be dropped by now. We still have y, so the object shouldn't have been dropped yet.
+    assert_eq!(NUM_DROPS.load(Relaxed), 0);
+
+    // Drop the remaining Arc.
+    drop(y);
+
+    // Now that y is dropped too, the object should've been dropped.
+    assert_eq!(NUM_DROPS.load(Relaxed), 1);
+}
+
+#[test]
+fn weak_upgrade() {
+    static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+
+    struct DetectDrop;
+    impl Drop for DetectDrop {
+        fn drop(&mut self) {
+            NUM_DROPS.fetch_add(1, Relaxed);
+        }
+    }
+
+    // Create an Arc with two weak pointers.
+    let x = Arc::new(("hello", DetectDrop));
+    let y = Arc::downgrade(&x);
+    let z = Arc::downgrade(&x);
+
+    let t = thread::spawn(move || {
+        // Weak pointer should be upgradable at this point.
+        let y = y.upgrade().unwrap();
+        assert_eq!(y.0, "hello");
+    });
+    assert_eq!(x.0, "hello");
+    t.join().unwrap();
+
+    // The data shouldn't be dropped yet, and the weak pointer should be upgradable.
+    assert_eq!(NUM_DROPS.load(Relaxed), 0);
+    assert!(z.upgrade().is_some());
+
+    drop(x);
+
+    // Now, the data should be dropped, and the weak pointer should no longer be upgradable.
+    assert_eq!(NUM_DROPS.load(Relaxed), 1);
+    assert!(z.upgrade().is_none());
+}
+
+#[test]
+fn arc_get_mut_and_make_mut() {
+    static NUM_DROPS: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Clone)]
+    struct DetectDrop(usize);
+    impl Drop for DetectDrop {
+        fn drop(&mut self) {
+            NUM_DROPS.fetch_add(1, Relaxed);
+        }
+    }
+
+    let mut x = Arc::new(DetectDrop(1));
+
+    // unique, so no clone is needed
+    Arc::get_mut(&mut x).unwrap().0 = 2;
+    Arc::make_mut(&mut x).0 = 3;
+    assert_eq!(x.0, 3);
+
+    // a Weak prevents get_mut
+    let weak = Arc::downgrade(&x);
+    assert!(Arc::get_mut(&mut x).is_none());
+    drop(weak);
+
+    // another Arc prevents get_mut, make_mut clones instead
+    let y = x.clone();
+    assert!(Arc::get_mut(&mut x).is_none());
+    Arc::make_mut(&mut x).0 = 4;
+    assert_eq!(x.0, 4);
+    assert_eq!(y.0, 3);
+    assert_eq!(NUM_DROPS.load(Relaxed), 0);
+
+    drop(y);
+    assert_eq!(NUM_DROPS.load(Relaxed), 1);
+    drop(x);
+    assert_eq!(NUM_DROPS.load(Relaxed), 2);
+}
+
+#[cfg(feature = "model")]
+#[test]
+fn model_arc() {
+    model::model(|| {
+        let x = Arc::new(String::from("hello"));
+        let weak = Arc::downgrade(&x);
+        let thread = thread::spawn({
+            let x = x.clone();
+            move || {
+                // the last Arc might be dropped by either thread
+                assert_eq!(*x, "hello");
+            }
+        });
+        let upgraded = weak.upgrade();
+        drop(x);
+        if let Some(upgraded) = upgraded {
+            assert_eq!(*upgraded, "hello");
+        }
+        thread.join().unwrap();
+        assert!(weak.upgrade().is_none());
+    });
+
+    // get_mut "locks" the weak count while another thread downgrades
+    model::model(|| {
+        let mut x = Arc::new(0);
+        let y = x.clone();
+        let thread = thread::spawn(move || {
+            let weak = Arc::downgrade(&y);
+            drop(y);
+            assert!(weak.upgrade().is_none_or(|y| *y <= 1));
+        });
+        // only unique once the other thread is done with its Arc and its Weak
+        if let Some(x) = Arc::get_mut(&mut x) {
+            *x += 1;
+        }
+        thread.join().unwrap();
+        *Arc::get_mut(&mut x).unwrap() += 1;
+        assert!(*x >= 1);
+    });
+}
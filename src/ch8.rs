@@ -0,0 +1,169 @@
+//! Operating System Primitives Summary
+//! - The atomic operations in a processor are enough to build a spin lock, but a lock that puts a waiting
+//!   thread to sleep needs help from the kernel, which is the only one that can put threads to sleep and wake them up.
+//! - The interface to the kernel is usually a library (like libc on Unix) that wraps the syscalls.
+//! - POSIX provides pthread_mutex_t, pthread_cond_t and friends, but those can't be moved after initialization,
+//!   which doesn't fit well with Rust's move semantics.
+//! - On Linux, a futex is a 32-bit atomic variable together with two syscalls:
+//!   - FUTEX_WAIT puts a thread to sleep, but only if the futex still has the expected value.
+//!     Checking and going to sleep happens atomically, so a wake-up operation can't be missed.
+//!   - FUTEX_WAKE wakes up a given number of threads waiting on the futex.
+//! - Spurious wake-ups are possible, so waiting always needs to happen in a loop that checks the condition again.
+//! - A futex is just an address, so no memory is allocated for it in the kernel until a thread actually waits on it.
+//! - Other operating systems have similar (but not identical) primitives,
+//!   like WaitOnAddress on Windows and __ulock_wait on macOS.
+//!
+//! Only compiled on Linux. [crate::atomic_wait] picks the right primitive for the current operating system.
+
+use std::ffi::c_long;
+
+use super::*;
+
+// libc is always linked on Linux by std, so we can call it without the libc crate
+extern "C" {
+    fn syscall(number: c_long, ...) -> c_long;
+}
+
+// the futex syscall number differs between architectures
+#[cfg(target_arch = "x86_64")]
+const SYS_FUTEX: c_long = 202;
+#[cfg(any(
+    target_arch = "aarch64",
+    target_arch = "riscv64",
+    target_arch = "loongarch64"
+))]
+const SYS_FUTEX: c_long = 98;
+#[cfg(any(target_arch = "x86", target_arch = "arm"))]
+const SYS_FUTEX: c_long = 240;
+#[cfg(any(target_arch = "powerpc", target_arch = "powerpc64"))]
+const SYS_FUTEX: c_long = 221;
+#[cfg(target_arch = "s390x")]
+const SYS_FUTEX: c_long = 238;
+#[cfg(any(target_arch = "sparc", target_arch = "sparc64"))]
+const SYS_FUTEX: c_long = 142;
+#[cfg(target_arch = "mips")]
+const SYS_FUTEX: c_long = 4238;
+#[cfg(all(target_arch = "mips64", target_pointer_width = "64"))]
+const SYS_FUTEX: c_long = 5194;
+#[cfg(all(target_arch = "mips64", target_pointer_width = "32"))]
+const SYS_FUTEX: c_long = 6194;
+// riscv32 and other newer 32-bit architectures only have futex_time64, which takes a different timespec
+#[cfg(not(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "riscv64",
+    target_arch = "loongarch64",
+    target_arch = "x86",
+    target_arch = "arm",
+    target_arch = "powerpc",
+    target_arch = "powerpc64",
+    target_arch = "s390x",
+    target_arch = "sparc",
+    target_arch = "sparc64",
+    target_arch = "mips",
+    target_arch = "mips64"
+)))]
+compile_error!("no futex syscall number for this architecture, add it to SYS_FUTEX");
+
+const FUTEX_WAIT: c_long = 0;
+const FUTEX_WAKE: c_long = 1;
+/// The futex is only used by threads of this process. Lets the kernel skip some bookkeeping.
+const FUTEX_PRIVATE_FLAG: c_long = 128;
+
+/// Puts the current thread to sleep if `a` still contains `expected`.
+/// Might return spuriously, so call this in a loop.
+pub fn futex_wait(a: &AtomicU32, expected: u32) {
+    // SAFETY: FUTEX_WAIT only reads the u32 at the address, and `a` is a valid AtomicU32 for the whole call
+    unsafe {
+        syscall(
+            SYS_FUTEX,
+            a.as_ptr(),
+            FUTEX_WAIT | FUTEX_PRIVATE_FLAG,
+            expected,
+            ptr::null::<()>(), // no timeout
+        );
+    }
+}
+
+/// The `struct timespec` FUTEX_WAIT takes as a relative timeout.
+#[repr(C)]
+struct Timespec {
+    tv_sec: c_long,
+    tv_nsec: c_long,
+}
+
+/// Like [futex_wait], but sleeps for at most `timeout`.
+/// Doesn't report whether it timed out, because spurious wake-ups mean the caller has to check the time itself anyway.
+pub fn futex_wait_timeout(a: &AtomicU32, expected: u32, timeout: Duration) {
+    let timeout = Timespec {
+        tv_sec: timeout.as_secs().try_into().unwrap_or(c_long::MAX),
+        tv_nsec: timeout.subsec_nanos() as c_long,
+    };
+
+    // SAFETY: FUTEX_WAIT only reads the u32 at the address and the timespec, both are valid for the whole call
+    unsafe {
+        syscall(
+            SYS_FUTEX,
+            a.as_ptr(),
+            FUTEX_WAIT | FUTEX_PRIVATE_FLAG,
+            expected,
+            &timeout as *const Timespec,
+        );
+    }
+}
+
+/// Wakes at most one thread sleeping in [futex_wait] on `a`.
+///
+/// `a` is only used as a key and is never dereferenced, so it may be dangling.
+/// This allows waking a thread after the memory of the futex might have been freed (by the woken thread).
+// the address is only passed to the kernel as a key, see above
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub fn futex_wake_one(a: *const AtomicU32) {
+    // SAFETY: FUTEX_WAKE only uses the address of `a` as a key, it doesn't access the memory
+    unsafe {
+        syscall(SYS_FUTEX, a, FUTEX_WAKE | FUTEX_PRIVATE_FLAG, 1);
+    }
+}
+
+/// Wakes every thread sleeping in [futex_wait] on `a`. `a` may be dangling, see [futex_wake_one].
+// the address is only passed to the kernel as a key, see above
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub fn futex_wake_all(a: *const AtomicU32) {
+    // SAFETY: FUTEX_WAKE only uses the address of `a` as a key, it doesn't access the memory
+    unsafe {
+        syscall(SYS_FUTEX, a, FUTEX_WAKE | FUTEX_PRIVATE_FLAG, i32::MAX);
+    }
+}
+
+#[test]
+fn futex_wait_and_wake() {
+    let a = AtomicU32::new(0);
+
+    thread::scope(|s| {
+        s.spawn(|| {
+            thread::sleep(Duration::from_millis(100));
+            a.store(1, Relaxed);
+            futex_wake_one(&a);
+        });
+
+        // futex_wait doesn't sleep when the value is not the expected value
+        futex_wait(&a, 1);
+
+        while a.load(Relaxed) == 0 {
+            futex_wait(&a, 0);
+        }
+    });
+
+    assert_eq!(a.load(Relaxed), 1);
+}
+
+#[test]
+fn futex_wait_timeout_returns() {
+    let a = AtomicU32::new(0);
+    let start = Instant::now();
+
+    // nobody wakes us up
+    futex_wait_timeout(&a, 0, Duration::from_millis(50));
+
+    assert!(start.elapsed() >= Duration::from_millis(50));
+}
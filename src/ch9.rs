@@ -0,0 +1,838 @@
+//! Building Our Own Locks Summary
+//! - The atomic-wait crate provides basic futex-like functionality that works on (recent versions of) all major operating systems.
+//!   In this crate [crate::atomic_wait] plays that role.
+//! - A minimal implementation of a mutex only needs two states, like our SpinLock from Chapter 4.
+//! - A three-state mutex, tracking whether there are waiting threads, can avoid unnecessary wake operations.
+//! - Spinning before going to sleep might in some cases be beneficial.
+//! - Benchmarks are useful, but also depend on a lot of factors. Designing a good benchmark is hard.
+//! - A condition variable can be implemented with just a notification counter; waiting on the counter value from
+//!   before unlocking the mutex makes sure no notification is missed.
+//! - A reader-writer lock can track its readers, and a waiting writer, in a single state word.
+//!   Blocking new readers while a writer is waiting prevents writer starvation.
+
+use std::ops::{Deref, DerefMut};
+
+use super::*;
+use crate::atomic_wait::{wait, wait_timeout, wake_all, wake_one};
+
+/// A mutex that puts waiting threads to sleep (see [crate::atomic_wait::wait]) instead of spinning like [crate::ch4::SpinLockFlag].
+pub struct Mutex<T> {
+    /// - 0: unlocked
+    /// - 1: locked, no other threads waiting
+    /// - 2: locked, other threads (maybe) waiting
+    state: AtomicU32,
+    value: UnsafeCell<T>,
+}
+unsafe impl<T> Sync for Mutex<T> where T: Send {}
+
+impl<T> Mutex<T> {
+    /// Number of times [Mutex::lock] spins before going to sleep.
+    const SPIN_LIMIT: u32 = 100;
+
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: AtomicU32::new(0), // unlocked
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        // fast path: unlocked -> locked without waiters
+        if self.state.compare_exchange(0, 1, Acquire, Relaxed).is_err() {
+            // the lock was already locked
+            self.lock_contended();
+        }
+
+        MutexGuard { mutex: self }
+    }
+
+    #[cold]
+    fn lock_contended(&self) {
+        // spin for a bit, but only while there are no waiters.
+        // if there are, the lock is probably held for a long time and spinning is a waste
+        let mut spin_count = 0;
+        while self.state.load(Relaxed) == 1 && spin_count < Self::SPIN_LIMIT {
+            spin_count += 1;
+            hint::spin_loop();
+        }
+
+        // try again, without marking the lock as having waiters
+        if self.state.compare_exchange(0, 1, Acquire, Relaxed).is_ok() {
+            return;
+        }
+
+        // mark the lock as having waiters and sleep until it is unlocked.
+        // when swap returns 0 we got the lock, but we don't know if there are other waiters, so it stays at 2
+        while self.state.swap(2, Acquire) != 0 {
+            wait(&self.state, 2);
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+/// Unlocks the [Mutex] when dropped. Just like [crate::ch4]'s `Guard`, it can only be created by [Mutex::lock].
+pub struct MutexGuard<'a, T> {
+    mutex: &'a Mutex<T>,
+}
+unsafe impl<T> Sync for MutexGuard<'_, T> where T: Sync {}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // SAFETY: The existence of this guard guarantees we've exclusively locked the mutex.
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: The existence of this guard guarantees we've exclusively locked the mutex.
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        // only make the wake syscall if a thread marked itself as waiting
+        if self.mutex.state.swap(0, Release) == 2 {
+            wake_one(&self.mutex.state);
+        }
+    }
+}
+
+/// A lock guard that a [Condvar] can unlock while waiting, and lock again afterwards.
+/// Implemented by [MutexGuard] and [crate::ch4]'s SpinLock `Guard`.
+pub trait CondvarGuard<'a>: Sized {
+    type Lock: 'a;
+    /// Unlocks the lock (by dropping the guard) and returns it so it can be passed to [CondvarGuard::relock].
+    fn unlock(self) -> &'a Self::Lock;
+    /// Locks the lock again.
+    /// # Errors
+    /// - When the lock is poisoned. The [PoisonError] still contains the guard
+    fn relock(lock: &'a Self::Lock) -> LockResult<Self>;
+}
+
+impl<'a, T> CondvarGuard<'a> for MutexGuard<'a, T> {
+    type Lock = Mutex<T>;
+    fn unlock(self) -> &'a Mutex<T> {
+        self.mutex
+    }
+    fn relock(lock: &'a Mutex<T>) -> LockResult<Self> {
+        // our Mutex doesn't support poisoning
+        Ok(lock.lock())
+    }
+}
+
+/// A condition variable that works with any [CondvarGuard], not just [std::sync::MutexGuard].
+///
+/// Instead of a queue of waiting threads there is only a counter that every notification changes.
+/// A thread waits (see [crate::atomic_wait::wait]) on the counter value it saw *before* unlocking the lock,
+/// so a notification that happens between unlocking and going to sleep is never lost.
+pub struct Condvar {
+    counter: AtomicU32,
+    /// Only used to skip the wake syscall when nobody is waiting.
+    num_waiters: AtomicUsize,
+}
+
+/// Whether [Condvar::wait_timeout] returned because the timeout elapsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WaitTimeoutResult(bool);
+impl WaitTimeoutResult {
+    pub fn timed_out(&self) -> bool {
+        self.0
+    }
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            counter: AtomicU32::new(0),
+            num_waiters: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn notify_one(&self) {
+        // Relaxed is enough: num_waiters is incremented while the lock is held,
+        // and a notifier is expected to have just locked (and changed) the same lock
+        if self.num_waiters.load(Relaxed) > 0 {
+            self.counter.fetch_add(1, Relaxed);
+            wake_one(&self.counter);
+        }
+    }
+
+    pub fn notify_all(&self) {
+        if self.num_waiters.load(Relaxed) > 0 {
+            self.counter.fetch_add(1, Relaxed);
+            wake_all(&self.counter);
+        }
+    }
+
+    /// Unlocks `guard`'s lock, sleeps until notified, and locks it again.
+    /// Might return spuriously, so call this in a loop (or use [Condvar::wait_while]).
+    /// # Errors
+    /// - When the lock is poisoned while relocking. The [PoisonError] still contains the guard
+    pub fn wait<'a, G: CondvarGuard<'a>>(&self, guard: G) -> LockResult<G> {
+        // incremented while still holding the lock, so a notifier that locks after us sees it
+        self.num_waiters.fetch_add(1, Relaxed);
+
+        let counter_value = self.counter.load(Relaxed);
+
+        // Unlock the lock by dropping the guard, but remember the lock so we can lock it again later.
+        let lock = guard.unlock();
+
+        // Wait, but only if the counter hasn't changed since unlocking.
+        wait(&self.counter, counter_value);
+
+        self.num_waiters.fetch_sub(1, Relaxed);
+
+        G::relock(lock)
+    }
+
+    /// Waits (handling spurious wake-ups) for as long as `condition` returns `true`.
+    /// # Errors
+    /// - When the lock is poisoned while relocking. The [PoisonError] still contains the guard
+    pub fn wait_while<'a, G, F>(&self, mut guard: G, mut condition: F) -> LockResult<G>
+    where
+        G: CondvarGuard<'a> + DerefMut,
+        F: FnMut(&mut G::Target) -> bool,
+    {
+        while condition(&mut *guard) {
+            guard = self.wait(guard)?;
+        }
+        Ok(guard)
+    }
+
+    /// Like [Condvar::wait] but gives up after `timeout`.
+    /// Might return spuriously before the timeout, see [WaitTimeoutResult::timed_out].
+    /// # Errors
+    /// - When the lock is poisoned while relocking. The [PoisonError] still contains the guard
+    pub fn wait_timeout<'a, G: CondvarGuard<'a>>(
+        &self,
+        guard: G,
+        timeout: Duration,
+    ) -> LockResult<(G, WaitTimeoutResult)> {
+        let start = Instant::now();
+
+        self.num_waiters.fetch_add(1, Relaxed);
+        let counter_value = self.counter.load(Relaxed);
+        let lock = guard.unlock();
+
+        wait_timeout(&self.counter, counter_value, timeout);
+
+        self.num_waiters.fetch_sub(1, Relaxed);
+
+        let result = WaitTimeoutResult(start.elapsed() >= timeout);
+        match G::relock(lock) {
+            Ok(guard) => Ok((guard, result)),
+            Err(poisoned) => Err(PoisonError::new((poisoned.into_inner(), result))),
+        }
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A reader-writer lock: any number of readers *or* one writer.
+///
+/// Everything is tracked in a single state word:
+/// - bit 0 ([RwLock::WRITER_WAITING]): a writer is waiting. New readers wait too, so a continuous stream of readers can't starve writers.
+/// - bit 1 ([RwLock::UPGRADABLE]): an [UpgradableReadGuard] exists. It shares the lock with readers, but not with another upgradable reader.
+/// - bits 2.. : the number of [ReadGuard]s times [RwLock::READER].
+/// - `u32::MAX` ([RwLock::WRITE_LOCKED]): write-locked. Every bit is set, so readers and upgradable readers wait.
+pub struct RwLock<T> {
+    state: AtomicU32,
+    /// Writers wait on this instead of [RwLock::state], so the (frequent) changes made by readers don't wake them up.
+    writer_wake_counter: AtomicU32,
+    value: UnsafeCell<T>,
+}
+// readers share the T between threads, so unlike Mutex<T> this also needs T: Sync
+unsafe impl<T> Sync for RwLock<T> where T: Send + Sync {}
+
+impl<T> RwLock<T> {
+    const WRITER_WAITING: u32 = 1;
+    const UPGRADABLE: u32 = 2;
+    const READER: u32 = 4;
+    const WRITE_LOCKED: u32 = u32::MAX;
+    /// Leaves room so the reader count can never overflow into [RwLock::WRITE_LOCKED].
+    const MAX_READERS_STATE: u32 = u32::MAX - 2 * Self::READER;
+
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: AtomicU32::new(0), // unlocked
+            writer_wake_counter: AtomicU32::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn read(&self) -> ReadGuard<'_, T> {
+        let mut s = self.state.load(Relaxed);
+        loop {
+            // no writer holds or waits for the lock
+            if s & Self::WRITER_WAITING == 0 {
+                assert!(s < Self::MAX_READERS_STATE, "too many readers");
+                match self
+                    .state
+                    .compare_exchange_weak(s, s + Self::READER, Acquire, Relaxed)
+                {
+                    Ok(_) => return ReadGuard { rwlock: self },
+                    Err(e) => s = e,
+                }
+            } else {
+                wait(&self.state, s);
+                s = self.state.load(Relaxed);
+            }
+        }
+    }
+
+    /// Returns [None] instead of waiting when a writer holds or waits for the lock.
+    pub fn try_read(&self) -> Option<ReadGuard<'_, T>> {
+        let mut s = self.state.load(Relaxed);
+        while s & Self::WRITER_WAITING == 0 {
+            assert!(s < Self::MAX_READERS_STATE, "too many readers");
+            match self
+                .state
+                .compare_exchange_weak(s, s + Self::READER, Acquire, Relaxed)
+            {
+                Ok(_) => return Some(ReadGuard { rwlock: self }),
+                Err(e) => s = e,
+            }
+        }
+        None
+    }
+
+    pub fn write(&self) -> WriteGuard<'_, T> {
+        let mut s = self.state.load(Relaxed);
+        loop {
+            // no readers, upgradable reader or writer. Take the lock, clearing the waiting bit.
+            // other waiting writers will set it again when they wake up
+            if s & !Self::WRITER_WAITING == 0 {
+                match self
+                    .state
+                    .compare_exchange(s, Self::WRITE_LOCKED, Acquire, Relaxed)
+                {
+                    Ok(_) => return WriteGuard { rwlock: self },
+                    Err(e) => {
+                        s = e;
+                        continue;
+                    }
+                }
+            }
+
+            // block new readers by setting the waiting bit
+            if s & Self::WRITER_WAITING == 0 {
+                if let Err(e) =
+                    self.state
+                        .compare_exchange(s, s | Self::WRITER_WAITING, Relaxed, Relaxed)
+                {
+                    s = e;
+                    continue;
+                }
+            }
+
+            // wait, if it's still locked
+            let w = self.writer_wake_counter.load(Acquire);
+            s = self.state.load(Relaxed);
+            if s & !Self::WRITER_WAITING != 0 {
+                wait(&self.writer_wake_counter, w);
+                s = self.state.load(Relaxed);
+            }
+        }
+    }
+
+    /// Returns [None] instead of waiting when the lock is held by anyone.
+    pub fn try_write(&self) -> Option<WriteGuard<'_, T>> {
+        let mut s = self.state.load(Relaxed);
+        while s & !Self::WRITER_WAITING == 0 {
+            match self
+                .state
+                .compare_exchange(s, Self::WRITE_LOCKED, Acquire, Relaxed)
+            {
+                Ok(_) => return Some(WriteGuard { rwlock: self }),
+                Err(e) => s = e,
+            }
+        }
+        None
+    }
+
+    /// A read lock that can later be turned into a write lock with [UpgradableReadGuard::upgrade],
+    /// without letting another writer in between. Only one upgradable reader can exist at a time.
+    pub fn upgradable_read(&self) -> UpgradableReadGuard<'_, T> {
+        let mut s = self.state.load(Relaxed);
+        loop {
+            if s & (Self::WRITER_WAITING | Self::UPGRADABLE) == 0 {
+                match self
+                    .state
+                    .compare_exchange_weak(s, s | Self::UPGRADABLE, Acquire, Relaxed)
+                {
+                    Ok(_) => return UpgradableReadGuard { rwlock: self },
+                    Err(e) => s = e,
+                }
+            } else {
+                wait(&self.state, s);
+                s = self.state.load(Relaxed);
+            }
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+
+    /// Wakes a writer waiting in [RwLock::write].
+    fn wake_writer(&self) {
+        self.writer_wake_counter.fetch_add(1, Release);
+        wake_one(&self.writer_wake_counter);
+    }
+}
+
+/// Shared access to the `T` of a [RwLock]. Created by [RwLock::read].
+pub struct ReadGuard<'a, T> {
+    rwlock: &'a RwLock<T>,
+}
+
+/// Shared access to the `T` of a [RwLock] that can be upgraded to exclusive access. Created by [RwLock::upgradable_read].
+pub struct UpgradableReadGuard<'a, T> {
+    rwlock: &'a RwLock<T>,
+}
+
+/// Exclusive access to the `T` of a [RwLock]. Created by [RwLock::write].
+pub struct WriteGuard<'a, T> {
+    rwlock: &'a RwLock<T>,
+}
+
+impl<T> Deref for ReadGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // SAFETY: there is no writer while a ReadGuard exists
+        unsafe { &*self.rwlock.value.get_shared() }
+    }
+}
+
+impl<T> Deref for UpgradableReadGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // SAFETY: there is no writer while an UpgradableReadGuard exists
+        unsafe { &*self.rwlock.value.get_shared() }
+    }
+}
+
+impl<T> Deref for WriteGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // SAFETY: the WriteGuard is the only guard
+        unsafe { &*self.rwlock.value.get() }
+    }
+}
+
+impl<T> DerefMut for WriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // SAFETY: the WriteGuard is the only guard
+        unsafe { &mut *self.rwlock.value.get() }
+    }
+}
+
+impl<T> Drop for ReadGuard<'_, T> {
+    fn drop(&mut self) {
+        let rwlock = self.rwlock;
+        let s = rwlock.state.fetch_sub(RwLock::<T>::READER, Release) - RwLock::<T>::READER;
+
+        // the last reader is gone and someone is waiting to write
+        let has_readers = s & !(RwLock::<T>::WRITER_WAITING | RwLock::<T>::UPGRADABLE) != 0;
+        if !has_readers && s & RwLock::<T>::WRITER_WAITING != 0 {
+            if s & RwLock::<T>::UPGRADABLE != 0 {
+                // the upgradable reader might be waiting (on the state) in UpgradableReadGuard::upgrade
+                wake_all(&rwlock.state);
+            } else {
+                rwlock.wake_writer();
+            }
+        }
+    }
+}
+
+impl<T> Drop for UpgradableReadGuard<'_, T> {
+    fn drop(&mut self) {
+        let rwlock = self.rwlock;
+        let s =
+            rwlock.state.fetch_and(!RwLock::<T>::UPGRADABLE, Release) & !RwLock::<T>::UPGRADABLE;
+
+        // no readers left and a writer waiting
+        if s == RwLock::<T>::WRITER_WAITING {
+            rwlock.wake_writer();
+        }
+
+        // wake threads waiting in RwLock::upgradable_read
+        wake_all(&rwlock.state);
+    }
+}
+
+impl<T> Drop for WriteGuard<'_, T> {
+    fn drop(&mut self) {
+        let rwlock = self.rwlock;
+        rwlock.state.store(0, Release);
+        rwlock.wake_writer();
+        wake_all(&rwlock.state);
+    }
+}
+
+impl<'a, T> UpgradableReadGuard<'a, T> {
+    /// Waits for the other readers to leave and turns this into a [WriteGuard].
+    /// New readers have to wait from now on, so this can't be starved either.
+    pub fn upgrade(self) -> WriteGuard<'a, T> {
+        let rwlock = self.rwlock;
+        // the lock is handed over to the WriteGuard, so don't unlock it
+        std::mem::forget(self);
+
+        let mut s = rwlock.state.load(Relaxed);
+        loop {
+            // only us left (and maybe waiting writers)
+            if s & !RwLock::<T>::WRITER_WAITING == RwLock::<T>::UPGRADABLE {
+                // Acquire matches the last reader's release-decrement
+                match rwlock
+                    .state
+                    .compare_exchange(s, RwLock::<T>::WRITE_LOCKED, Acquire, Relaxed)
+                {
+                    Ok(_) => return WriteGuard { rwlock },
+                    Err(e) => s = e,
+                }
+            } else if s & RwLock::<T>::WRITER_WAITING == 0 {
+                // block new readers
+                if let Err(e) = rwlock.state.compare_exchange(
+                    s,
+                    s | RwLock::<T>::WRITER_WAITING,
+                    Relaxed,
+                    Relaxed,
+                ) {
+                    s = e;
+                }
+            } else {
+                // the last reader wakes us up (see ReadGuard::drop)
+                wait(&rwlock.state, s);
+                s = rwlock.state.load(Relaxed);
+            }
+        }
+    }
+
+    /// Turns this into a normal [ReadGuard], letting another thread take the upgradable read lock.
+    pub fn downgrade(self) -> ReadGuard<'a, T> {
+        let rwlock = self.rwlock;
+        std::mem::forget(self);
+
+        // one more reader, no upgradable reader
+        rwlock
+            .state
+            .fetch_add(RwLock::<T>::READER - RwLock::<T>::UPGRADABLE, Release);
+        wake_all(&rwlock.state);
+
+        ReadGuard { rwlock }
+    }
+}
+
+impl<'a, T> WriteGuard<'a, T> {
+    /// Atomically turns this into a [ReadGuard]. No other writer can get in between.
+    pub fn downgrade(self) -> ReadGuard<'a, T> {
+        let rwlock = self.rwlock;
+        std::mem::forget(self);
+
+        // one reader. This clears the waiting bit, so wake a writer to set it again
+        rwlock.state.store(RwLock::<T>::READER, Release);
+        rwlock.wake_writer();
+        wake_all(&rwlock.state);
+
+        ReadGuard { rwlock }
+    }
+}
+
+#[test]
+fn mutex() {
+    let mutex = Mutex::new(Vec::new());
+    let thread_count = 8;
+    let pushes_per_thread = 1000;
+
+    thread::scope(|s| {
+        for i in 0..thread_count {
+            let mutex = &mutex;
+            s.spawn(move || {
+                for _ in 0..pushes_per_thread {
+                    mutex.lock().push(i);
+                }
+            });
+        }
+    });
+
+    let data = mutex.into_inner();
+    assert_eq!(data.len(), thread_count * pushes_per_thread);
+    for i in 0..thread_count {
+        assert_eq!(data.iter().filter(|&&x| x == i).count(), pushes_per_thread);
+    }
+}
+
+#[test]
+fn mutex_sleeps_while_locked() {
+    let mutex = Mutex::new(0);
+
+    thread::scope(|s| {
+        let mut guard = mutex.lock();
+
+        let waiter = s.spawn(|| *mutex.lock() += 1);
+
+        // hold the lock long enough for the other thread to go to sleep
+        thread::sleep(Duration::from_millis(100));
+        *guard += 1;
+        drop(guard);
+
+        waiter.join().unwrap();
+    });
+
+    assert_eq!(mutex.into_inner(), 2);
+}
+
+#[test]
+fn condvar() {
+    let mutex = Mutex::new(0);
+    let condvar = Condvar::new();
+
+    let mut wakeups = 0;
+
+    thread::scope(|s| {
+        s.spawn(|| {
+            thread::sleep(Duration::from_secs(1));
+            *mutex.lock() = 123;
+            condvar.notify_one();
+        });
+
+        let mut m = mutex.lock();
+        while *m < 100 {
+            m = condvar.wait(m).unwrap();
+            wakeups += 1;
+        }
+
+        assert_eq!(*m, 123);
+    });
+
+    // Check that the main thread actually did wait (not busy-loop),
+    // while still allowing for a few spurious wake ups.
+    assert!(wakeups < 10);
+}
+
+#[test]
+fn condvar_spurious_wakeups() {
+    let mutex = Mutex::new(false);
+    let condvar = Condvar::new();
+
+    thread::scope(|s| {
+        s.spawn(|| {
+            // notifications without changing the condition look like spurious wake-ups to the waiter
+            for _ in 0..10 {
+                thread::sleep(Duration::from_millis(10));
+                condvar.notify_all();
+            }
+            *mutex.lock() = true;
+            condvar.notify_all();
+        });
+
+        let is_ready = condvar.wait_while(mutex.lock(), |is_ready| !*is_ready);
+        assert!(*is_ready.unwrap());
+    });
+}
+
+#[test]
+fn condvar_no_lost_notifications() {
+    // ping-pong a counter between two threads. a single lost notification would leave both waiting forever,
+    // so every wait has a (generous) timeout that must never be hit
+    let mutex = Mutex::new(0);
+    let condvar = Condvar::new();
+    let rounds = 1000;
+
+    thread::scope(|s| {
+        for parity in [0, 1] {
+            let (mutex, condvar) = (&mutex, &condvar);
+            s.spawn(move || {
+                let mut counter = mutex.lock();
+                while *counter < rounds {
+                    if *counter % 2 == parity {
+                        *counter += 1;
+                        condvar.notify_one();
+                    } else {
+                        let result;
+                        (counter, result) = condvar
+                            .wait_timeout(counter, Duration::from_secs(10))
+                            .unwrap();
+                        assert!(!result.timed_out(), "lost a notification");
+                    }
+                }
+            });
+        }
+    });
+
+    assert_eq!(mutex.into_inner(), rounds);
+}
+
+#[test]
+fn condvar_wait_timeout() {
+    let mutex = Mutex::new(());
+    let condvar = Condvar::new();
+
+    let (_guard, result) = condvar
+        .wait_timeout(mutex.lock(), Duration::from_millis(50))
+        .unwrap();
+    assert!(result.timed_out());
+}
+
+#[test]
+fn condvar_with_spin_lock() {
+    use crate::ch4::SpinLock;
+
+    let lock = SpinLock::new(Vec::new());
+    let condvar = Condvar::new();
+
+    thread::scope(|s| {
+        s.spawn(|| {
+            for i in 0..3 {
+                thread::sleep(Duration::from_millis(10));
+                lock.lock().unwrap().push(i);
+                condvar.notify_one();
+            }
+        });
+
+        let data = condvar
+            .wait_while(lock.lock().unwrap(), |data| data.len() < 3)
+            .unwrap();
+        assert_eq!(*data, [0, 1, 2]);
+    });
+}
+
+#[test]
+fn rwlock() {
+    let rwlock = RwLock::new(0);
+    let thread_count = 4;
+    let writes_per_thread = 1000;
+
+    thread::scope(|s| {
+        for _ in 0..thread_count {
+            s.spawn(|| {
+                for _ in 0..writes_per_thread {
+                    *rwlock.write() += 1;
+                }
+            });
+            s.spawn(|| {
+                let mut last = 0;
+                for _ in 0..writes_per_thread {
+                    let value = *rwlock.read();
+                    assert!(value >= last);
+                    last = value;
+                }
+            });
+        }
+    });
+
+    assert_eq!(rwlock.into_inner(), thread_count * writes_per_thread);
+}
+
+#[test]
+fn rwlock_try_read_and_try_write() {
+    let rwlock = RwLock::new(0);
+
+    let read = rwlock.try_read().unwrap();
+    let read_2 = rwlock.try_read().unwrap();
+    assert!(rwlock.try_write().is_none());
+    drop((read, read_2));
+
+    let mut write = rwlock.try_write().unwrap();
+    *write += 1;
+    assert!(rwlock.try_read().is_none());
+    assert!(rwlock.try_write().is_none());
+    drop(write);
+
+    assert_eq!(*rwlock.try_read().unwrap(), 1);
+}
+
+#[test]
+fn rwlock_readers_cant_starve_writers() {
+    let rwlock = RwLock::new(0);
+    let is_done = AtomicBool::new(false);
+
+    thread::scope(|s| {
+        // there is always at least one reader holding the lock
+        for _ in 0..4 {
+            s.spawn(|| {
+                while !is_done.load(Relaxed) {
+                    let _read = rwlock.read();
+                    thread::sleep(Duration::from_millis(1));
+                }
+            });
+        }
+
+        thread::sleep(Duration::from_millis(20));
+
+        let start = Instant::now();
+        *rwlock.write() += 1;
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        is_done.store(true, Relaxed);
+    });
+
+    assert_eq!(rwlock.into_inner(), 1);
+}
+
+#[test]
+fn rwlock_upgrade() {
+    let rwlock = RwLock::new(0);
+
+    thread::scope(|s| {
+        let read = rwlock.read();
+        let upgradable = rwlock.upgradable_read();
+
+        // upgradable readers share with readers, but not with each other
+        assert!(rwlock.try_read().is_some());
+        assert!(rwlock.try_write().is_none());
+
+        let upgrader = s.spawn(move || {
+            let mut write = upgradable.upgrade();
+            *write += 1;
+            let read = write.downgrade();
+            *read
+        });
+
+        // the upgrade waits for the reader to leave
+        thread::sleep(Duration::from_millis(50));
+        assert!(!upgrader.is_finished());
+        // and new readers can't get in meanwhile
+        assert!(rwlock.try_read().is_none());
+        drop(read);
+
+        assert_eq!(upgrader.join().unwrap(), 1);
+    });
+
+    let upgradable = rwlock.upgradable_read();
+    let read = upgradable.downgrade();
+    let upgradable = rwlock.upgradable_read();
+    assert_eq!((*read, *upgradable), (1, 1));
+}
+
+#[test]
+fn rwlock_downgrade() {
+    let rwlock = RwLock::new(Vec::new());
+
+    thread::scope(|s| {
+        let mut write = rwlock.write();
+        write.push(1);
+
+        let writer = s.spawn(|| rwlock.write().push(2));
+
+        // no writer can get in between
+        let read = write.downgrade();
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(*read, [1]);
+        drop(read);
+
+        writer.join().unwrap();
+    });
+
+    assert_eq!(rwlock.into_inner(), [1, 2]);
+}
@@ -0,0 +1,94 @@
+//! A tiny executor, so futures like [crate::ch5::Receiver] can be awaited without an async runtime.
+//! - [block_on] polls a single future on the current thread, and parks the thread while the future is pending.
+//! - The [Waker] given to the future unparks that thread again, from whichever thread wakes it.
+//! - There is no task queue and no reactor: anything the future waits for has to be done by another thread.
+
+use std::{
+    future::Future,
+    pin::pin,
+    sync::Arc,
+    task::{Context, Poll, Wake, Waker},
+};
+
+use super::*;
+
+/// Wakes a thread by unparking it.
+struct ThreadWaker(thread::Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// A [Waker] that unparks the current thread.
+/// Lets blocking code wait on the same waker slot that futures register in.
+pub fn thread_waker() -> Waker {
+    Waker::from(Arc::new(ThreadWaker(thread::current())))
+}
+
+/// Runs `future` to completion on the current thread.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let waker = thread_waker();
+    let mut cx = Context::from_waker(&waker);
+
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            // a wake before this park leaves the unpark token, so park returns right away and nothing is missed.
+            // park can also return spuriously, which just causes an extra poll
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+#[test]
+fn block_on_ready() {
+    assert_eq!(block_on(async { 1 + 2 }), 3);
+}
+
+#[test]
+fn block_on_woken_from_another_thread() {
+    /// Pending until `is_done` is set by another thread, which wakes the waker it was given.
+    struct WaitForFlag<'a> {
+        is_done: &'a AtomicBool,
+        waker: &'a Mutex<Option<Waker>>,
+    }
+    impl Future for WaitForFlag<'_> {
+        type Output = ();
+        fn poll(self: std::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            // register before checking, so a flag set after the check will wake us
+            *self.waker.lock().unwrap() = Some(cx.waker().clone());
+            if self.is_done.load(Acquire) {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        }
+    }
+
+    let is_done = AtomicBool::new(false);
+    let waker = Mutex::new(None::<Waker>);
+
+    thread::scope(|s| {
+        s.spawn(|| {
+            thread::sleep(Duration::from_millis(50));
+            is_done.store(true, Release);
+            if let Some(waker) = waker.lock().unwrap().take() {
+                waker.wake();
+            }
+        });
+
+        block_on(WaitForFlag {
+            is_done: &is_done,
+            waker: &waker,
+        });
+    });
+
+    assert!(is_done.load(Relaxed));
+}
@@ -1,17 +1,105 @@
+//! The locks, channels and reference counting from "Rust Atomics and Locks", usable as a library.
+//! - [sync], [channel] and [arc] are the public API, re-exporting the implementations from the chapter modules.
+//! - The chapter modules (`ch4`, `ch5`, ...) are the teaching layer: every implementation next to the book's summary of the chapter.
+//!   They (and the building blocks like `atomic_wait`) are hidden from the docs, their paths are not part of the API.
+//! - [lock_free] has the data structures without a lock, and the memory reclamation they need.
+//! - [bench] and [litmus] measure them. The binary runs both from the command line.
+
 pub(crate) use std::{
-    cell::UnsafeCell,
     collections::VecDeque,
     mem::MaybeUninit,
     ptr,
-    sync::{
-        atomic::{Ordering::*, *},
-        Arc, Condvar, Mutex, MutexGuard, PoisonError,
-    },
+    sync::{LockResult, Mutex, MutexGuard, PoisonError, TryLockError, TryLockResult},
+    time::{Duration, Instant},
+};
+
+// with the `model` feature every atomic, UnsafeCell and thread in the crate is instrumented by the model checker
+#[cfg(feature = "model")]
+pub(crate) use model::{
+    atomic::{Ordering::*, *},
+    cell::UnsafeCell,
+    hint, thread,
+};
+#[cfg(not(feature = "model"))]
+pub(crate) use std::{
+    cell::UnsafeCell,
+    hint,
+    sync::atomic::{Ordering::*, *},
     thread,
-    time::Duration,
 };
 
-mod ch3;
-mod ch4;
-mod ch5;
-mod ch6;
+/// [UnsafeCell::get] for a pointer that is only read through.
+/// The model checker's UnsafeCell has this as a method, so concurrent reads aren't reported as a data race.
+#[cfg(not(feature = "model"))]
+pub(crate) trait UnsafeCellExt<T> {
+    fn get_shared(&self) -> *const T;
+}
+#[cfg(not(feature = "model"))]
+impl<T> UnsafeCellExt<T> for UnsafeCell<T> {
+    fn get_shared(&self) -> *const T {
+        self.get()
+    }
+}
+
+#[doc(hidden)]
+pub mod atomic_wait;
+pub mod bench;
+#[doc(hidden)]
+pub mod cache_padded;
+#[doc(hidden)]
+pub mod ch3;
+#[doc(hidden)]
+pub mod ch4;
+#[doc(hidden)]
+pub mod ch5;
+#[doc(hidden)]
+pub mod ch6;
+#[cfg(target_os = "linux")]
+#[doc(hidden)]
+pub mod ch8;
+#[doc(hidden)]
+pub mod ch9;
+#[doc(hidden)]
+pub mod executor;
+pub mod lock_free;
+#[cfg(feature = "model")]
+pub mod model;
+
+/// Locks and condition variables.
+pub mod sync {
+    pub use crate::{
+        ch4::{
+            queue_lock::{ClhGuard, McsGuard},
+            ticket_lock::Guard as TicketLockGuard,
+            Backoff, ClhLock, Guard as SpinLockGuard, McsLock, SpinLock, SpinLockFlag, TicketLock,
+        },
+        ch9::{
+            Condvar, CondvarGuard, Mutex, MutexGuard, ReadGuard, RwLock, UpgradableReadGuard,
+            WaitTimeoutResult, WriteGuard,
+        },
+    };
+}
+
+/// Channels for sending messages between threads.
+pub mod channel {
+    pub use crate::ch5::{error, simple, simple_channel, SimpleChannel};
+
+    pub mod oneshot {
+        //! Channels for a single message.
+        //! [channel] returns a [Sender] and [Receiver] pair, [OneshotChannel] and [borrowed::Channel] live wherever the caller puts them.
+
+        pub use crate::ch5::{
+            borrowed, channel, OneshotChannel, Receive, Receiver, RecvError, RecvTimeoutError,
+            Sender, TryRecvError,
+        };
+    }
+
+    pub use crate::ch5::{mpmc, spsc};
+}
+
+pub use ch3::litmus;
+
+/// Reference counting.
+pub mod arc {
+    pub use crate::ch6::{Arc, Weak};
+}
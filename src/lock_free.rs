@@ -0,0 +1,60 @@
+//! Lock-free data structures, built on [AtomicPtr] compare-and-exchange loops instead of a lock.
+//! - [TreiberStack]: a stack, where every push and pop swings the pointer to the top node.
+//! - [MsQueue]: the Michael-Scott queue, a linked list with a dummy node so pushing and popping touch different ends.
+//! - [hazard]: hazard pointers, which decide when a node that was popped can be freed.
+//! - [epoch]: epoch-based reclamation, the other way to decide that. Cheaper per operation, but one stalled thread holds back every free.
+//!
+//! Without a lock a thread can still be reading a node when another thread pops it, so nodes are retired instead of freed.
+//! That also prevents ABA: a node's address can't be reused while a thread that loaded it is about to compare-and-exchange it.
+
+use super::*;
+
+pub mod epoch;
+pub mod hazard;
+mod list;
+pub mod queue;
+pub mod stack;
+
+pub use queue::MsQueue;
+pub use stack::TreiberStack;
+
+/// A node that was retired (or deferred), behind a pointer that doesn't know its type. Dropping it frees the node.
+struct Garbage {
+    pointer: *mut (),
+    /// Frees `pointer`, which is a `Box<T>` for the `T` it was created as.
+    free: unsafe fn(*mut ()),
+}
+
+impl Garbage {
+    /// # Safety
+    /// - `pointer` came from [Box::into_raw], and nothing uses it anymore once the [Garbage] is dropped
+    unsafe fn new<T: Send>(pointer: *mut T) -> Self {
+        Self {
+            pointer: pointer.cast(),
+            free: free_box::<T>,
+        }
+    }
+}
+
+impl Drop for Garbage {
+    fn drop(&mut self) {
+        // SAFETY: see Garbage::new
+        unsafe { (self.free)(self.pointer) };
+    }
+}
+
+/// # Safety
+/// - `pointer` came from [Box::into_raw] of a `Box<T>`, and isn't used again
+unsafe fn free_box<T>(pointer: *mut ()) {
+    drop(Box::from_raw(pointer.cast::<T>()));
+}
+
+/// Counts how often it is dropped, to check that every retired (or deferred) node is freed exactly once.
+#[cfg(test)]
+struct Counted<'a>(&'a AtomicUsize);
+#[cfg(test)]
+impl Drop for Counted<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_add(1, Relaxed);
+    }
+}
@@ -0,0 +1,503 @@
+//! Epoch-based reclamation: a node is freed once every thread that could have loaded it has moved on.
+//! - Threads [LocalHandle::pin] (or [pin] on the default collector) before loading from a data structure, and the [Guard] unpins them.
+//!   A pinned thread announces the global epoch it saw.
+//! - [Guard::defer_destroy] puts an unlinked node in the thread's local bag. A full bag is sealed with the current epoch
+//!   and handed to the [Collector].
+//! - The global epoch only advances when every pinned thread has seen the current one. So once it is two epochs past a bag's,
+//!   every thread that was pinned when the bag's nodes were unlinked has unpinned, and the bag is freed.
+//! - A collector created with [Collector::with_garbage_count] counts the nodes deferred but not freed yet, see [Collector::garbage_count].
+//!
+//! Pinning is only a store and a fence, cheaper than protecting every pointer like [super::hazard] does.
+//! But a thread that stays pinned stops the epoch, and with it every free.
+
+use std::marker::PhantomData;
+
+use super::{
+    list::{AppendOnlyList, Entry, TakeAllList},
+    *,
+};
+
+/// The deferred nodes of one thread, sealed with the global epoch after they were unlinked.
+struct SealedBag {
+    epoch: usize,
+    deferred: Vec<Garbage>,
+}
+
+/// A registered thread, see [Collector::register]. Locals are never freed before their [Collector], only reused.
+struct Local {
+    /// `epoch << 1 | PINNED` while pinned, 0 while not.
+    state: AtomicUsize,
+    /// Only used by the thread that holds the [LocalHandle].
+    owner: UnsafeCell<Owner>,
+}
+
+/// The state of a [Local] that only its own thread touches.
+#[derive(Default)]
+struct Owner {
+    bag: Vec<Garbage>,
+    guard_count: usize,
+    pin_count: usize,
+    /// Set when the [LocalHandle] is dropped while a [Guard] still exists. The last [Guard] releases the [Local] instead.
+    is_handle_dropped: bool,
+}
+
+/// The global epoch, every registered thread, and the sealed bags waiting for the epoch to advance.
+pub struct Collector {
+    epoch: AtomicUsize,
+    /// Every local ever registered.
+    locals: AppendOnlyList<Local>,
+    /// Only ever taken all at once by [Collector::collect].
+    sealed_bags: TakeAllList<SealedBag>,
+    /// [None] unless created with [Collector::with_garbage_count].
+    garbage_count: Option<AtomicUsize>,
+}
+
+// SAFETY: deferred nodes are Send (see Guard::defer_destroy), and a local's Owner is only touched by its own thread
+unsafe impl Send for Collector {}
+unsafe impl Sync for Collector {}
+
+impl Default for Collector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Collector {
+    /// A thread's bag is sealed and handed to the collector when it holds this many nodes.
+    const BAG_CAPACITY: usize = 64;
+    /// Every this many pins, the pinning thread tries to advance the epoch and free the expired bags.
+    const COLLECT_INTERVAL: usize = 128;
+    const PINNED: usize = 1;
+
+    /// A collector that doesn't count its garbage, see [Collector::with_garbage_count].
+    pub const fn new() -> Self {
+        Self {
+            epoch: AtomicUsize::new(0),
+            locals: AppendOnlyList::new(),
+            sealed_bags: TakeAllList::new(),
+            garbage_count: None,
+        }
+    }
+
+    /// A collector that counts the nodes deferred but not freed yet, to find garbage that never gets freed
+    /// (a thread that stays pinned, or a bag that is never sealed). Every defer and free updates the shared counter.
+    pub const fn with_garbage_count() -> Self {
+        Self {
+            epoch: AtomicUsize::new(0),
+            locals: AppendOnlyList::new(),
+            sealed_bags: TakeAllList::new(),
+            garbage_count: Some(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Registers the current thread. Reuses the `Local` of a dropped [LocalHandle] when there is one.
+    pub fn register(&self) -> LocalHandle<'_> {
+        let local = self.locals.claim(|| Local {
+            state: AtomicUsize::new(0),
+            owner: UnsafeCell::new(Owner::default()),
+        });
+        LocalHandle::new(self, local)
+    }
+
+    /// The current global epoch.
+    pub fn epoch(&self) -> usize {
+        self.epoch.load(Relaxed)
+    }
+
+    /// Advances the global epoch if every pinned thread has seen it. Returns the global epoch.
+    fn try_advance(&self) -> usize {
+        let epoch = self.epoch.load(Relaxed);
+        // pairs with the fence in Local::pin: either we see the thread pinned, or it sees the epoch (and everything unlinked before it)
+        fence(SeqCst);
+
+        let is_behind = |local: &Entry<Local>| {
+            let state = local.state.load(Relaxed);
+            state & Self::PINNED != 0 && state >> 1 != epoch
+        };
+        if self.locals.iter().any(is_behind) {
+            return epoch;
+        }
+        // the threads that were pinned in an older epoch are done with what they loaded
+        fence(Acquire);
+
+        // fails when another thread advanced it first, which is just as good
+        match self
+            .epoch
+            .compare_exchange(epoch, epoch + 1, Release, Relaxed)
+        {
+            Ok(_) => epoch + 1,
+            Err(actual) => actual,
+        }
+    }
+
+    /// Seals `deferred` with the current epoch and adds it to the sealed bags.
+    fn seal(&self, deferred: Vec<Garbage>) {
+        if deferred.is_empty() {
+            return;
+        }
+        // the nodes were unlinked before this, so a thread pinned in this epoch or later never sees them
+        fence(SeqCst);
+        self.sealed_bags.push(SealedBag {
+            epoch: self.epoch.load(Relaxed),
+            deferred,
+        });
+    }
+
+    /// Tries to advance the epoch, then frees every sealed bag that is two epochs old.
+    pub fn collect(&self) {
+        let epoch = self.try_advance();
+        let mut bags = self.sealed_bags.take_all();
+        bags.retain(|bag| {
+            let is_expired = bag.epoch + 2 <= epoch;
+            if is_expired {
+                // every thread that was pinned when the nodes were unlinked has unpinned
+                self.free(std::mem::take(&mut bag.deferred));
+            }
+            !is_expired
+        });
+        self.sealed_bags.put_back(bags);
+    }
+
+    fn free(&self, deferred: Vec<Garbage>) {
+        if let Some(garbage_count) = &self.garbage_count {
+            garbage_count.fetch_sub(deferred.len(), Relaxed);
+        }
+        // dropping the nodes frees them, see Guard::defer_destroy
+        drop(deferred);
+    }
+
+    /// The number of nodes deferred but not freed yet, in the local bags and the sealed ones.
+    /// [None] when the collector doesn't count them, see [Collector::with_garbage_count].
+    pub fn garbage_count(&self) -> Option<usize> {
+        self.garbage_count
+            .as_ref()
+            .map(|garbage_count| garbage_count.load(Relaxed))
+    }
+}
+
+impl Local {
+    /// # Safety
+    /// - Only called by the thread that holds the [LocalHandle] (or the last [Guard]), and the reference isn't held across a call that can pin
+    #[allow(clippy::mut_from_ref)]
+    unsafe fn owner(&self) -> &mut Owner {
+        &mut *self.owner.get()
+    }
+}
+
+impl Entry<Local> {
+    fn pin<'a>(&'a self, collector: &'a Collector) -> Guard<'a> {
+        // SAFETY: only the owning thread pins
+        let owner = unsafe { self.owner() };
+        owner.guard_count += 1;
+        let guard = Guard {
+            collector,
+            local: self,
+            _not_send: PhantomData,
+        };
+        if owner.guard_count > 1 {
+            // already pinned
+            return guard;
+        }
+
+        owner.pin_count = owner.pin_count.wrapping_add(1);
+        let should_collect = owner.pin_count % Collector::COLLECT_INTERVAL == 0;
+        self.state.store(
+            collector.epoch.load(Relaxed) << 1 | Collector::PINNED,
+            Relaxed,
+        );
+        // pairs with the fence in Collector::try_advance: the store above is visible before we load anything from a data structure
+        fence(SeqCst);
+
+        if should_collect {
+            collector.collect();
+        }
+        guard
+    }
+
+    /// Unpins, and releases the local if the [LocalHandle] is gone too.
+    fn unpin(&self, collector: &Collector) {
+        // SAFETY: only the owning thread unpins
+        let owner = unsafe { self.owner() };
+        owner.guard_count -= 1;
+        if owner.guard_count > 0 {
+            return;
+        }
+        // Release: everything we loaded while pinned happens-before the epoch advances past us
+        self.state.store(0, Release);
+        if owner.is_handle_dropped {
+            self.unregister(collector);
+        }
+    }
+
+    /// Seals the local bag and gives the local back to the collector, for another thread to register.
+    fn unregister(&self, collector: &Collector) {
+        // SAFETY: only the owning thread unregisters, and nothing of it is borrowed anymore
+        let owner = std::mem::take(unsafe { self.owner() });
+        collector.seal(owner.bag);
+        self.release();
+    }
+}
+
+/// The registration of a thread with a [Collector]. Dropping it seals the thread's local bag.
+pub struct LocalHandle<'a> {
+    collector: &'a Collector,
+    local: &'a Entry<Local>,
+    /// The local state belongs to the thread that registered.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<'a> LocalHandle<'a> {
+    fn new(collector: &'a Collector, local: &'a Entry<Local>) -> Self {
+        Self {
+            collector,
+            local,
+            _not_send: PhantomData,
+        }
+    }
+
+    /// Pins the thread until the [Guard] is dropped. Pinning again while pinned is cheap, and the thread stays pinned until the last [Guard] is dropped.
+    pub fn pin(&self) -> Guard<'a> {
+        self.local.pin(self.collector)
+    }
+
+    pub fn is_pinned(&self) -> bool {
+        // SAFETY: we are the owning thread
+        unsafe { self.local.owner().guard_count > 0 }
+    }
+}
+
+impl Drop for LocalHandle<'_> {
+    fn drop(&mut self) {
+        // SAFETY: we are the owning thread
+        let owner = unsafe { self.local.owner() };
+        if owner.guard_count > 0 {
+            // the last Guard releases it
+            owner.is_handle_dropped = true;
+        } else {
+            self.local.unregister(self.collector);
+        }
+    }
+}
+
+/// Keeps the thread pinned. Nodes loaded from a data structure while the [Guard] exists aren't freed before it is dropped.
+pub struct Guard<'a> {
+    collector: &'a Collector,
+    local: &'a Entry<Local>,
+    /// Unpinning has to happen on the thread that pinned.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl Guard<'_> {
+    /// Frees `pointer` once every thread that is pinned now has unpinned.
+    /// # Safety
+    /// - `pointer` came from [Box::into_raw], and it isn't reachable from the data structure anymore,
+    ///   so a thread that pins after this won't find it
+    /// - It is only deferred once
+    pub unsafe fn defer_destroy<T: Send>(&self, pointer: *mut T) {
+        if let Some(garbage_count) = &self.collector.garbage_count {
+            garbage_count.fetch_add(1, Relaxed);
+        }
+
+        // SAFETY: a Guard only exists on the owning thread, and sealing the bag doesn't pin
+        let owner = unsafe { self.local.owner() };
+        owner.bag.push(Garbage::new(pointer));
+        if owner.bag.len() >= Collector::BAG_CAPACITY {
+            let bag = std::mem::take(&mut owner.bag);
+            self.collector.seal(bag);
+        }
+    }
+
+    /// Seals the local bag (even when it isn't full) and tries to free the expired bags.
+    pub fn flush(&self) {
+        // SAFETY: a Guard only exists on the owning thread
+        let bag = std::mem::take(unsafe { &mut self.local.owner().bag });
+        self.collector.seal(bag);
+        self.collector.collect();
+    }
+
+    pub fn collector(&self) -> &Collector {
+        self.collector
+    }
+}
+
+impl Drop for Guard<'_> {
+    fn drop(&mut self) {
+        self.local.unpin(self.collector);
+    }
+}
+
+#[cfg(not(feature = "model"))]
+static DEFAULT_COLLECTOR: Collector = Collector::new();
+
+#[cfg(not(feature = "model"))]
+thread_local! {
+    static HANDLE: LocalHandle<'static> = DEFAULT_COLLECTOR.register();
+}
+
+/// The collector behind [pin]. Its garbage is only freed by pinning threads, it is never dropped.
+#[cfg(not(feature = "model"))]
+pub fn default_collector() -> &'static Collector {
+    &DEFAULT_COLLECTOR
+}
+
+/// Pins the current thread with the [default_collector], registering the thread the first time.
+#[cfg(not(feature = "model"))]
+pub fn pin() -> Guard<'static> {
+    HANDLE
+        .try_with(LocalHandle::pin)
+        // the thread's handle was already dropped (pinning from a thread local's destructor). a temporary handle
+        // is released by the Guard
+        .unwrap_or_else(|_| DEFAULT_COLLECTOR.register().pin())
+}
+
+#[test]
+fn epoch_defers_until_unpinned() {
+    let drop_count = AtomicUsize::new(0);
+    let collector = Collector::with_garbage_count();
+    let (pinned, other) = (collector.register(), collector.register());
+
+    let pinned_guard = pinned.pin();
+    let guard = other.pin();
+    unsafe { guard.defer_destroy(Box::into_raw(Box::new(Counted(&drop_count)))) };
+    guard.flush();
+    drop(guard);
+    assert_eq!(collector.garbage_count(), Some(1));
+
+    // a thread pinned before the node was deferred holds the epoch back
+    for _ in 0..10 {
+        other.pin().flush();
+    }
+    assert_eq!(drop_count.load(Relaxed), 0);
+    assert!(collector.epoch() <= 1);
+
+    drop(pinned_guard);
+    for _ in 0..2 {
+        other.pin().flush();
+    }
+    assert_eq!(drop_count.load(Relaxed), 1);
+    assert_eq!(collector.garbage_count(), Some(0));
+    assert_eq!(Collector::new().garbage_count(), None);
+
+    // a full bag is sealed without a flush, and the collector frees what is left
+    let guard = pinned.pin();
+    for _ in 0..Collector::BAG_CAPACITY + 1 {
+        unsafe { guard.defer_destroy(Box::into_raw(Box::new(Counted(&drop_count)))) };
+    }
+    drop(guard);
+    drop((pinned, other));
+    drop(collector);
+    assert_eq!(drop_count.load(Relaxed), Collector::BAG_CAPACITY + 2);
+}
+
+#[test]
+fn epoch_handles_are_reused() {
+    let collector = Collector::new();
+    let guard = {
+        let handle = collector.register();
+        let guard = handle.pin();
+        assert!(handle.is_pinned());
+        // outlives its handle: the local is released when the guard is dropped
+        guard
+    };
+    let _second = collector.register();
+    drop(guard);
+    let _third = collector.register();
+
+    assert_eq!(collector.locals.len(), 2);
+}
+
+#[test]
+fn epoch_stress() {
+    let drop_count = AtomicUsize::new(0);
+    let collector = Collector::new();
+    let thread_count = 4;
+    let swaps_per_thread = 10_000;
+    let shared = AtomicPtr::new(Box::into_raw(Box::new((0, Counted(&drop_count)))));
+
+    thread::scope(|s| {
+        for thread in 0..thread_count {
+            let (collector, shared, drop_count) = (&collector, &shared, &drop_count);
+            s.spawn(move || {
+                let handle = collector.register();
+                for i in 0..swaps_per_thread {
+                    let guard = handle.pin();
+                    // read the current node, which another thread may unlink and defer at the same time
+                    let current = shared.load(Acquire);
+                    assert!(unsafe { (*current).0 } <= thread_count * swaps_per_thread);
+
+                    let new = Box::into_raw(Box::new((
+                        thread * swaps_per_thread + i,
+                        Counted(drop_count),
+                    )));
+                    let old = shared.swap(new, AcqRel);
+                    unsafe { guard.defer_destroy(old) };
+                }
+            });
+        }
+    });
+
+    drop(collector);
+    // every node but the last one was freed exactly once
+    assert_eq!(drop_count.load(Relaxed), thread_count * swaps_per_thread);
+    drop(unsafe { Box::from_raw(shared.into_inner()) });
+}
+
+#[cfg(not(feature = "model"))]
+#[test]
+fn epoch_default_collector() {
+    let drop_count = AtomicUsize::new(0);
+    let guard = pin();
+    unsafe { guard.defer_destroy(Box::into_raw(Box::new(Counted(&drop_count)))) };
+    guard.flush();
+    drop(guard);
+
+    // other tests can keep the default collector's epoch back for a while
+    while drop_count.load(Relaxed) == 0 {
+        pin().flush();
+        thread::yield_now();
+    }
+}
+
+#[cfg(feature = "model")]
+#[test]
+fn model_epoch() {
+    use crate::ch6::Arc;
+
+    /// Writes to its cell when it is dropped, so freeing it while another thread reads it is a data race.
+    struct Node(UnsafeCell<usize>);
+    impl Drop for Node {
+        fn drop(&mut self) {
+            unsafe { *self.0.get() = 0 };
+        }
+    }
+
+    model::model(|| {
+        let collector = Arc::new(Collector::new());
+        let shared = Arc::new(AtomicPtr::new(Box::into_raw(Box::new(Node(
+            UnsafeCell::new(1),
+        )))));
+
+        let reader = thread::spawn({
+            let (collector, shared) = (Arc::clone(&collector), Arc::clone(&shared));
+            move || {
+                let handle = collector.register();
+                let _guard = handle.pin();
+                let node = shared.load(Acquire);
+                assert_ne!(unsafe { *(*node).0.get_shared() }, 0);
+            }
+        });
+
+        let handle = collector.register();
+        let old = shared.swap(Box::into_raw(Box::new(Node(UnsafeCell::new(2)))), AcqRel);
+        let guard = handle.pin();
+        unsafe { guard.defer_destroy(old) };
+        guard.flush();
+        drop(guard);
+        for _ in 0..2 {
+            handle.pin().flush();
+        }
+
+        reader.join().unwrap();
+        drop(unsafe { Box::from_raw(shared.swap(ptr::null_mut(), Relaxed)) });
+    });
+}
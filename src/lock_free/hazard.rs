@@ -0,0 +1,181 @@
+//! Hazard pointers: a thread publishes the pointer it is about to dereference, and retired nodes are only freed when no thread published them.
+//! - A [Domain] owns the hazard records of every thread that uses it, and the nodes retired but not freed yet.
+//! - [Domain::hazard_pointer] claims a record, [HazardPointer::protect] publishes a pointer loaded from an [AtomicPtr].
+//! - [Domain::retire] takes a node that was unlinked, and frees it once a scan finds it unprotected.
+//!
+//! A node can't be freed (or its address reused) while a thread protects it, which also rules out ABA on a protected pointer.
+
+use super::{
+    list::{AppendOnlyList, Entry, TakeAllList},
+    *,
+};
+
+/// A slot for one published pointer. Records are never freed before their [Domain], only reused.
+struct Record {
+    /// The published pointer, null when nothing is protected.
+    pointer: AtomicPtr<()>,
+}
+
+/// The hazard records and retired nodes shared by the threads of one data structure.
+pub struct Domain {
+    /// Every record ever claimed.
+    records: AppendOnlyList<Record>,
+    /// The retired nodes, only ever taken all at once by a scan.
+    retired: TakeAllList<Garbage>,
+    retired_count: AtomicUsize,
+}
+
+// SAFETY: retired nodes are Send (see Domain::retire), and only freed by one thread
+unsafe impl Send for Domain {}
+unsafe impl Sync for Domain {}
+
+impl Default for Domain {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Domain {
+    /// A scan happens once this many nodes are retired, or twice the number of records if that is more.
+    /// So at least half of a scan's nodes are unprotected and freed.
+    const SCAN_THRESHOLD: usize = 64;
+
+    pub const fn new() -> Self {
+        Self {
+            records: AppendOnlyList::new(),
+            retired: TakeAllList::new(),
+            retired_count: AtomicUsize::new(0),
+        }
+    }
+
+    /// Claims an unused record, or adds a new one. The [HazardPointer] gives it back when it is dropped.
+    pub fn hazard_pointer(&self) -> HazardPointer<'_> {
+        let record = self.records.claim(|| Record {
+            pointer: AtomicPtr::new(ptr::null_mut()),
+        });
+        HazardPointer { record }
+    }
+
+    /// Frees `pointer` once no [HazardPointer] of this domain protects it.
+    /// # Safety
+    /// - `pointer` came from [Box::into_raw], and it isn't reachable from the data structure anymore,
+    ///   so a thread that starts protecting it now won't find it
+    /// - It is only retired once
+    pub unsafe fn retire<T: Send>(&self, pointer: *mut T) {
+        // counted before it is pushed, so a scan that frees it never makes the count drop below zero
+        let retired_count = self.retired_count.fetch_add(1, Relaxed) + 1;
+        self.retired.push(Garbage::new(pointer));
+
+        let threshold = Self::SCAN_THRESHOLD.max(2 * self.records.len());
+        if retired_count >= threshold {
+            self.scan();
+        }
+    }
+
+    /// Frees every retired node that isn't protected, and puts the others back.
+    fn scan(&self) {
+        let mut retired = self.retired.take_all();
+        if retired.is_empty() {
+            // another thread is scanning them
+            return;
+        }
+
+        // pairs with the SeqCst store and load in HazardPointer::protect: either that thread sees the node unlinked,
+        // or we see its hazard
+        fence(SeqCst);
+        let protected: Vec<_> = self
+            .records
+            .iter()
+            .map(|record| record.pointer.load(SeqCst))
+            .filter(|pointer| !pointer.is_null())
+            .collect();
+
+        // dropping an unprotected node frees it: retired means unreachable, and no thread protected it before it became unreachable
+        let mut freed = 0;
+        retired.retain(|node| {
+            let is_protected = protected.contains(&node.pointer);
+            freed += usize::from(!is_protected);
+            is_protected
+        });
+
+        self.retired_count.fetch_sub(freed, Relaxed);
+        self.retired.put_back(retired);
+    }
+
+    /// The number of nodes retired but not freed yet.
+    pub fn retired_count(&self) -> usize {
+        self.retired_count.load(Relaxed)
+    }
+}
+
+/// A claimed hazard record. It protects at most one pointer at a time.
+pub struct HazardPointer<'a> {
+    record: &'a Entry<Record>,
+}
+
+impl HazardPointer<'_> {
+    /// Loads `source` and protects the pointer, so it isn't freed before [HazardPointer::reset] (or until something else is protected).
+    /// The pointer is only safe to dereference if it was reachable from `source` while protected, which this checks by loading `source` again.
+    pub fn protect<T>(&mut self, source: &AtomicPtr<T>) -> *mut T {
+        let mut pointer = source.load(Relaxed);
+        loop {
+            // SeqCst: a scan that misses this hazard (see Domain::scan) started after it, so the node wasn't retired yet
+            self.record.pointer.store(pointer.cast(), SeqCst);
+            let current = source.load(SeqCst);
+            if current == pointer {
+                // Acquire for the node's contents, on top of the loads above
+                fence(Acquire);
+                return pointer;
+            }
+            pointer = current;
+        }
+    }
+
+    /// Stops protecting the pointer.
+    pub fn reset(&mut self) {
+        self.record.pointer.store(ptr::null_mut(), Release);
+    }
+}
+
+impl Drop for HazardPointer<'_> {
+    fn drop(&mut self) {
+        self.reset();
+        self.record.release();
+    }
+}
+
+#[test]
+fn hazard_pointer_delays_free() {
+    let drop_count = AtomicUsize::new(0);
+    let protected_drop_count = AtomicUsize::new(0);
+    let domain = Domain::new();
+    let source = AtomicPtr::new(Box::into_raw(Box::new(Counted(&protected_drop_count))));
+
+    let mut hazard_pointer = domain.hazard_pointer();
+    let pointer = hazard_pointer.protect(&source);
+    // unlink it, then retire enough nodes to force scans
+    source.store(ptr::null_mut(), Relaxed);
+    unsafe { domain.retire(pointer) };
+    for _ in 0..2 * Domain::SCAN_THRESHOLD {
+        unsafe { domain.retire(Box::into_raw(Box::new(Counted(&drop_count)))) };
+    }
+    assert!(drop_count.load(Relaxed) > 0);
+    assert_eq!(protected_drop_count.load(Relaxed), 0);
+
+    // the next scan frees it
+    drop(hazard_pointer);
+    for _ in 0..Domain::SCAN_THRESHOLD {
+        unsafe { domain.retire(Box::into_raw(Box::new(Counted(&drop_count)))) };
+    }
+    assert_eq!(protected_drop_count.load(Relaxed), 1);
+    assert!(domain.retired_count() < Domain::SCAN_THRESHOLD);
+
+    // records are reused
+    drop(domain.hazard_pointer());
+    drop(domain.hazard_pointer());
+    assert_eq!(domain.records.len(), 1);
+
+    // the domain frees whatever is left
+    drop(domain);
+    assert_eq!(drop_count.load(Relaxed), 3 * Domain::SCAN_THRESHOLD);
+}
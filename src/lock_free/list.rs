@@ -0,0 +1,213 @@
+//! The two lists behind the reclaimers in [super::hazard] and [super::epoch].
+//! - [AppendOnlyList]: one entry per thread (hazard records, epoch locals). Entries are claimed and released, but never removed
+//!   before the list is dropped, so any thread can walk the list without protecting anything.
+//! - [TakeAllList]: the garbage waiting to be freed. Pushed one value at a time, but only ever taken all at once, so there is no ABA.
+
+use std::{marker::PhantomData, ops::Deref};
+
+use super::*;
+
+/// An entry of an [AppendOnlyList], claimed by one owner at a time.
+pub(super) struct Entry<T> {
+    is_active: AtomicBool,
+    /// The entry that was pushed before this one. Never changes after the push.
+    next: *mut Entry<T>,
+    value: T,
+}
+
+impl<T> Entry<T> {
+    /// Gives the entry back, for [AppendOnlyList::claim] to reuse.
+    pub(super) fn release(&self) {
+        self.is_active.store(false, Release);
+    }
+}
+
+impl<T> Deref for Entry<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+/// A list that only grows: pushed like a Treiber stack and never popped.
+pub(super) struct AppendOnlyList<T> {
+    head: AtomicPtr<Entry<T>>,
+    len: AtomicUsize,
+    /// The list owns its entries.
+    _owns: PhantomData<Box<Entry<T>>>,
+}
+
+impl<T> AppendOnlyList<T> {
+    pub(super) const fn new() -> Self {
+        Self {
+            head: AtomicPtr::new(ptr::null_mut()),
+            len: AtomicUsize::new(0),
+            _owns: PhantomData,
+        }
+    }
+
+    /// Claims a released entry, or pushes a new one holding `new()`. [Entry::release] gives it back.
+    pub(super) fn claim(&self, new: impl FnOnce() -> T) -> &Entry<T> {
+        let released = self.iter().find(|entry| {
+            !entry.is_active.load(Relaxed)
+                && entry
+                    .is_active
+                    .compare_exchange(false, true, Acquire, Relaxed)
+                    .is_ok()
+        });
+        if let Some(entry) = released {
+            return entry;
+        }
+
+        let entry = Box::into_raw(Box::new(Entry {
+            is_active: AtomicBool::new(true),
+            next: self.head.load(Relaxed),
+            value: new(),
+        }));
+        // SAFETY: nobody else has the new entry until it is pushed, and the Release below publishes it
+        while let Err(head) =
+            self.head
+                .compare_exchange(unsafe { (*entry).next }, entry, Release, Relaxed)
+        {
+            unsafe { (*entry).next = head };
+        }
+        self.len.fetch_add(1, Relaxed);
+
+        // SAFETY: entries are only freed when the list is dropped
+        unsafe { &*entry }
+    }
+
+    /// Every entry, claimed or not.
+    pub(super) fn iter(&self) -> impl Iterator<Item = &Entry<T>> + '_ {
+        let mut next = self.head.load(Acquire);
+        std::iter::from_fn(move || {
+            // SAFETY: entries are only freed when the list is dropped
+            let entry = unsafe { next.as_ref() }?;
+            next = entry.next;
+            Some(entry)
+        })
+    }
+
+    /// The number of entries ever pushed.
+    pub(super) fn len(&self) -> usize {
+        self.len.load(Relaxed)
+    }
+}
+
+impl<T> Drop for AppendOnlyList<T> {
+    fn drop(&mut self) {
+        let mut entry = *self.head.get_mut();
+        while !entry.is_null() {
+            // SAFETY: every borrowed entry borrows the list, so we have exclusive access to every entry
+            let node = unsafe { Box::from_raw(entry) };
+            entry = node.next;
+        }
+    }
+}
+
+struct Node<T> {
+    value: T,
+    next: *mut Node<T>,
+}
+
+/// A list that is pushed one value at a time, but only ever taken all at once with [TakeAllList::take_all].
+pub(super) struct TakeAllList<T> {
+    head: AtomicPtr<Node<T>>,
+    /// The list owns its values.
+    _owns: PhantomData<Box<Node<T>>>,
+}
+
+impl<T> TakeAllList<T> {
+    pub(super) const fn new() -> Self {
+        Self {
+            head: AtomicPtr::new(ptr::null_mut()),
+            _owns: PhantomData,
+        }
+    }
+
+    pub(super) fn push(&self, value: T) {
+        let node = Box::into_raw(Box::new(Node {
+            value,
+            next: ptr::null_mut(),
+        }));
+        self.push_nodes(node, node);
+    }
+
+    /// Takes every value. Empty when there was nothing to take, or another thread took it first.
+    pub(super) fn take_all(&self) -> Taken<T> {
+        Taken {
+            first: self.head.swap(ptr::null_mut(), Acquire),
+        }
+    }
+
+    /// Pushes the values that were taken (and kept) back onto the list.
+    pub(super) fn put_back(&self, taken: Taken<T>) {
+        let first = std::mem::ManuallyDrop::new(taken).first;
+        if first.is_null() {
+            return;
+        }
+        let mut last = first;
+        // SAFETY: we own every node in the taken list
+        while unsafe { !(*last).next.is_null() } {
+            last = unsafe { (*last).next };
+        }
+        self.push_nodes(first, last);
+    }
+
+    /// Pushes the nodes from `first` to `last`.
+    fn push_nodes(&self, first: *mut Node<T>, last: *mut Node<T>) {
+        let mut head = self.head.load(Relaxed);
+        loop {
+            // SAFETY: the nodes aren't shared until they are pushed
+            unsafe { (*last).next = head };
+            match self
+                .head
+                .compare_exchange_weak(head, first, Release, Relaxed)
+            {
+                Ok(_) => return,
+                Err(actual) => head = actual,
+            }
+        }
+    }
+}
+
+impl<T> Drop for TakeAllList<T> {
+    fn drop(&mut self) {
+        drop(Taken {
+            first: *self.head.get_mut(),
+        });
+    }
+}
+
+/// The values taken out of a [TakeAllList]. Dropping it drops them, [TakeAllList::put_back] gives them back.
+pub(super) struct Taken<T> {
+    first: *mut Node<T>,
+}
+
+impl<T> Taken<T> {
+    pub(super) fn is_empty(&self) -> bool {
+        self.first.is_null()
+    }
+
+    /// Drops every value `keep` returns false for.
+    pub(super) fn retain(&mut self, mut keep: impl FnMut(&mut T) -> bool) {
+        let mut node = std::mem::replace(&mut self.first, ptr::null_mut());
+        while !node.is_null() {
+            // SAFETY: we own every node in the list
+            let next = unsafe { (*node).next };
+            if keep(unsafe { &mut (*node).value }) {
+                unsafe { (*node).next = self.first };
+                self.first = node;
+            } else {
+                drop(unsafe { Box::from_raw(node) });
+            }
+            node = next;
+        }
+    }
+}
+
+impl<T> Drop for Taken<T> {
+    fn drop(&mut self) {
+        self.retain(|_| false);
+    }
+}
@@ -0,0 +1,249 @@
+//! The Michael-Scott queue: a singly linked list from `head` to `tail`, where `head` always points at a dummy node.
+//! - A push links the new node after the last one, then swings `tail` to it.
+//!   `tail` can lag one node behind, and any thread that notices helps swing it.
+//! - A pop swings `head` to the node after the dummy, which becomes the new dummy. Its value is the one popped.
+
+use super::{hazard::Domain, *};
+
+struct Node<T> {
+    /// Uninitialized in the dummy node, and moved out by the thread that makes the node the dummy.
+    value: MaybeUninit<T>,
+    next: AtomicPtr<Node<T>>,
+}
+
+impl<T> Node<T> {
+    fn new(value: MaybeUninit<T>) -> *mut Self {
+        Box::into_raw(Box::new(Self {
+            value,
+            next: AtomicPtr::new(ptr::null_mut()),
+        }))
+    }
+}
+
+/// A lock-free multi-producer multi-consumer queue. Popped nodes are freed through the queue's own hazard pointer [Domain].
+pub struct MsQueue<T> {
+    /// The dummy node.
+    head: AtomicPtr<Node<T>>,
+    /// The last node, or the one before it while a push is in progress.
+    tail: AtomicPtr<Node<T>>,
+    domain: Domain,
+}
+
+// SAFETY: values are sent to whichever thread pops them, and the queue is only shared through atomics
+unsafe impl<T: Send> Send for MsQueue<T> {}
+unsafe impl<T: Send> Sync for MsQueue<T> {}
+
+impl<T> Default for MsQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> MsQueue<T> {
+    pub fn new() -> Self {
+        let dummy = Node::new(MaybeUninit::uninit());
+        Self {
+            head: AtomicPtr::new(dummy),
+            tail: AtomicPtr::new(dummy),
+            domain: Domain::new(),
+        }
+    }
+
+    pub fn push(&self, value: T) {
+        let node = Node::new(MaybeUninit::new(value));
+        let mut hazard_pointer = self.domain.hazard_pointer();
+        loop {
+            let tail = hazard_pointer.protect(&self.tail);
+            // SAFETY: the node is protected, so it isn't freed even if it is popped now
+            let tail_node = unsafe { &*tail };
+            let next = tail_node.next.load(Acquire);
+
+            if !next.is_null() {
+                // tail is lagging behind, help the other push before trying again
+                _ = self.tail.compare_exchange(tail, next, Release, Relaxed);
+                continue;
+            }
+
+            // Release: a thread that pops the node sees its value
+            if tail_node
+                .next
+                .compare_exchange(ptr::null_mut(), node, Release, Relaxed)
+                .is_ok()
+            {
+                // fails when another thread already helped
+                _ = self.tail.compare_exchange(tail, node, Release, Relaxed);
+                return;
+            }
+        }
+    }
+
+    pub fn pop(&self) -> Option<T>
+    where
+        T: Send,
+    {
+        let mut head_hazard = self.domain.hazard_pointer();
+        let mut next_hazard = self.domain.hazard_pointer();
+        loop {
+            let head = head_hazard.protect(&self.head);
+            // SAFETY: the dummy is protected, so it isn't freed even if another thread pops it now
+            let next = next_hazard.protect(unsafe { &(*head).next });
+            // the next node is only retired after head moved past it, so if head is still the dummy, next wasn't retired
+            // before it was protected
+            if self.head.load(SeqCst) != head {
+                continue;
+            }
+            if next.is_null() {
+                return None;
+            }
+
+            // never let head pass tail: tail is lagging behind, help the push first
+            let tail = self.tail.load(Acquire);
+            if head == tail {
+                _ = self.tail.compare_exchange(tail, next, Release, Relaxed);
+                continue;
+            }
+
+            if self
+                .head
+                .compare_exchange(head, next, Acquire, Relaxed)
+                .is_ok()
+            {
+                // SAFETY: only the thread that makes a node the dummy moves its value out, and a dummy's value is never read again
+                let value = unsafe { ptr::read(&(*next).value).assume_init() };
+                head_hazard.reset();
+                next_hazard.reset();
+                // SAFETY: the old dummy is unreachable now, and only we unlinked it
+                unsafe { self.domain.retire(head) };
+                return Some(value);
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        let mut hazard_pointer = self.domain.hazard_pointer();
+        let head = hazard_pointer.protect(&self.head);
+        // SAFETY: the node is protected
+        unsafe { (*head).next.load(Relaxed).is_null() }
+    }
+}
+
+impl<T> Drop for MsQueue<T> {
+    fn drop(&mut self) {
+        // the dummy's value is uninitialized or was moved out, every node after it still has its value
+        let dummy = unsafe { Box::from_raw(*self.head.get_mut()) };
+        let mut node = dummy.next.load(Relaxed);
+        while !node.is_null() {
+            // SAFETY: we have exclusive access to every node still in the queue
+            let mut boxed = unsafe { Box::from_raw(node) };
+            unsafe { boxed.value.assume_init_drop() };
+            node = *boxed.next.get_mut();
+        }
+    }
+}
+
+#[test]
+fn ms_queue() {
+    let queue = MsQueue::new();
+    assert_eq!(queue.pop(), None);
+    for value in 0..3 {
+        queue.push(value);
+    }
+    assert_eq!(queue.pop(), Some(0));
+    queue.push(3);
+    assert_eq!(queue.pop(), Some(1));
+    assert_eq!(queue.pop(), Some(2));
+    assert_eq!(queue.pop(), Some(3));
+    assert!(queue.is_empty());
+
+    // values left in the queue, and nodes that are waiting to be freed, are dropped exactly once
+    let value = crate::ch6::Arc::new(());
+    let queue = MsQueue::new();
+    for _ in 0..1000 {
+        queue.push(value.clone());
+        queue.push(value.clone());
+        drop(queue.pop());
+    }
+    drop(queue);
+    let mut value = value;
+    assert!(crate::ch6::Arc::get_mut(&mut value).is_some());
+}
+
+#[test]
+fn ms_queue_stress() {
+    let queue = MsQueue::new();
+    let producer_count = 4;
+    let consumer_count = 4;
+    let values_per_producer = 20_000;
+    let popped_count = AtomicUsize::new(0);
+    let total = producer_count * values_per_producer;
+
+    let popped: Vec<Vec<(usize, usize)>> = thread::scope(|s| {
+        for producer in 0..producer_count {
+            let queue = &queue;
+            s.spawn(move || {
+                for i in 0..values_per_producer {
+                    queue.push((producer, i));
+                }
+            });
+        }
+
+        let consumers: Vec<_> = (0..consumer_count)
+            .map(|_| {
+                let (queue, popped_count) = (&queue, &popped_count);
+                s.spawn(move || {
+                    let mut popped = Vec::new();
+                    while popped_count.load(Relaxed) < total {
+                        if let Some(value) = queue.pop() {
+                            popped.push(value);
+                            popped_count.fetch_add(1, Relaxed);
+                        } else {
+                            thread::yield_now();
+                        }
+                    }
+                    popped
+                })
+            })
+            .collect();
+        consumers.into_iter().map(|c| c.join().unwrap()).collect()
+    });
+
+    // every consumer sees the values of one producer in the order they were pushed
+    for values in &popped {
+        let mut last = vec![None; producer_count];
+        for &(producer, i) in values {
+            assert!(last[producer] < Some(i));
+            last[producer] = Some(i);
+        }
+    }
+
+    // no value is lost or popped twice
+    let mut values: Vec<(usize, usize)> = popped.into_iter().flatten().collect();
+    values.sort();
+    let expected: Vec<(usize, usize)> = (0..producer_count)
+        .flat_map(|producer| (0..values_per_producer).map(move |i| (producer, i)))
+        .collect();
+    assert_eq!(values, expected);
+    assert!(queue.is_empty());
+}
+
+#[cfg(feature = "model")]
+#[test]
+fn model_ms_queue() {
+    use crate::ch6::Arc;
+
+    model::model(|| {
+        let queue = Arc::new(MsQueue::new());
+        let thread = thread::spawn({
+            let queue = Arc::clone(&queue);
+            move || {
+                queue.push(1);
+                queue.push(2);
+            }
+        });
+        let first = queue.pop();
+        thread.join().unwrap();
+        let mut popped: Vec<_> = first.into_iter().collect();
+        popped.extend(std::iter::from_fn(|| queue.pop()));
+        assert_eq!(popped, [1, 2]);
+    });
+}
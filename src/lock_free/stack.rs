@@ -0,0 +1,186 @@
+//! The Treiber stack: a singly linked list where `head` points at the top node.
+
+use std::mem::ManuallyDrop;
+
+use super::{hazard::Domain, *};
+
+struct Node<T> {
+    /// Moved out by the thread that pops the node, so the node is freed without dropping it.
+    value: ManuallyDrop<T>,
+    /// The node below this one. Never changes after the push.
+    next: *mut Node<T>,
+}
+
+// SAFETY: a node is only sent to another thread to be freed, after the value was moved out
+unsafe impl<T: Send> Send for Node<T> {}
+
+/// A lock-free stack. Popped nodes are freed through the stack's own hazard pointer [Domain].
+pub struct TreiberStack<T> {
+    head: AtomicPtr<Node<T>>,
+    domain: Domain,
+}
+
+// SAFETY: values are sent to whichever thread pops them, and the stack is only shared through atomics
+unsafe impl<T: Send> Send for TreiberStack<T> {}
+unsafe impl<T: Send> Sync for TreiberStack<T> {}
+
+impl<T> Default for TreiberStack<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> TreiberStack<T> {
+    pub const fn new() -> Self {
+        Self {
+            head: AtomicPtr::new(ptr::null_mut()),
+            domain: Domain::new(),
+        }
+    }
+
+    pub fn push(&self, value: T) {
+        let node = Box::into_raw(Box::new(Node {
+            value: ManuallyDrop::new(value),
+            next: self.head.load(Relaxed),
+        }));
+
+        // SAFETY: nobody else has the node until the compare-and-exchange publishes it
+        // Release: a thread that pops the node sees it initialized
+        while let Err(head) =
+            self.head
+                .compare_exchange_weak(unsafe { (*node).next }, node, Release, Relaxed)
+        {
+            unsafe { (*node).next = head };
+        }
+    }
+
+    pub fn pop(&self) -> Option<T>
+    where
+        T: Send,
+    {
+        let mut hazard_pointer = self.domain.hazard_pointer();
+        loop {
+            let head = hazard_pointer.protect(&self.head);
+            if head.is_null() {
+                return None;
+            }
+
+            // SAFETY: the node is protected, so it isn't freed even if another thread pops it now
+            let next = unsafe { (*head).next };
+            // protected, so head can't have been freed and pushed again at the same address (no ABA)
+            if self
+                .head
+                .compare_exchange_weak(head, next, Relaxed, Relaxed)
+                .is_ok()
+            {
+                hazard_pointer.reset();
+                // SAFETY: only the thread that unlinked the node moves the value out, and the node is freed without dropping it
+                let value = unsafe { ptr::read(&(*head).value) };
+                // SAFETY: the node is unreachable now, and only we unlinked it
+                unsafe { self.domain.retire(head) };
+                return Some(ManuallyDrop::into_inner(value));
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Relaxed).is_null()
+    }
+}
+
+impl<T> Drop for TreiberStack<T> {
+    fn drop(&mut self) {
+        let mut node = *self.head.get_mut();
+        while !node.is_null() {
+            // SAFETY: we have exclusive access to every node still on the stack
+            let mut boxed = unsafe { Box::from_raw(node) };
+            unsafe { ManuallyDrop::drop(&mut boxed.value) };
+            node = boxed.next;
+        }
+    }
+}
+
+#[test]
+fn treiber_stack() {
+    let stack = TreiberStack::new();
+    assert_eq!(stack.pop(), None);
+    for value in 0..3 {
+        stack.push(value);
+    }
+    assert_eq!(stack.pop(), Some(2));
+    stack.push(3);
+    assert_eq!(stack.pop(), Some(3));
+    assert_eq!(stack.pop(), Some(1));
+    assert_eq!(stack.pop(), Some(0));
+    assert!(stack.is_empty());
+
+    // values left on the stack, and values in nodes that are waiting to be freed, are dropped exactly once
+    let value = crate::ch6::Arc::new(());
+    let stack = TreiberStack::new();
+    for _ in 0..1000 {
+        stack.push(value.clone());
+        stack.push(value.clone());
+        drop(stack.pop());
+    }
+    drop(stack);
+    let mut value = value;
+    assert!(crate::ch6::Arc::get_mut(&mut value).is_some());
+}
+
+#[test]
+fn treiber_stack_stress() {
+    let stack = TreiberStack::new();
+    let thread_count = 8;
+    let values_per_thread = 20_000;
+
+    let popped: Vec<Vec<usize>> = thread::scope(|s| {
+        let threads: Vec<_> = (0..thread_count)
+            .map(|thread| {
+                let stack = &stack;
+                s.spawn(move || {
+                    // push and pop at the same time, so nodes are popped while other threads are reading them
+                    let mut popped = Vec::new();
+                    for i in 0..values_per_thread {
+                        stack.push(thread * values_per_thread + i);
+                        if i % 2 == 0 {
+                            popped.extend(stack.pop());
+                        }
+                    }
+                    popped
+                })
+            })
+            .collect();
+        threads.into_iter().map(|t| t.join().unwrap()).collect()
+    });
+
+    // no value is lost or popped twice
+    let mut values: Vec<usize> = popped.into_iter().flatten().collect();
+    values.extend(std::iter::from_fn(|| stack.pop()));
+    values.sort();
+    assert_eq!(
+        values,
+        (0..thread_count * values_per_thread).collect::<Vec<_>>()
+    );
+}
+
+#[cfg(feature = "model")]
+#[test]
+fn model_treiber_stack() {
+    use crate::ch6::Arc;
+
+    model::model(|| {
+        let stack = Arc::new(TreiberStack::new());
+        stack.push(1);
+        let thread = thread::spawn({
+            let stack = Arc::clone(&stack);
+            move || {
+                stack.push(2);
+                stack.pop()
+            }
+        });
+        let popped = stack.pop();
+        let mut values = vec![popped, thread.join().unwrap(), stack.pop()];
+        values.sort();
+        assert_eq!(values, [None, Some(1), Some(2)]);
+    });
+}
@@ -1 +1,226 @@
-fn main() {}
+//! Benchmarks and demos for the crate's locks and channels, next to the ones from std.
+//! Run without arguments for the usage.
+
+use std::{env, process::ExitCode, time::Duration};
+
+use atomics_and_locks_book::{
+    bench::{self, Bench, Report, Scenario},
+    litmus::{self, Orderings, Shape},
+    sync::{self, ClhLock, McsLock, SpinLockFlag, TicketLock},
+};
+
+const USAGE: &str = "\
+usage:
+    bench lock --kind spin|flag|ticket|mcs|clh|futex|std [OPTIONS]
+    bench queue-locks [--duration S] [--warmup S] [--format text|csv|json]
+    bench channel --kind oneshot|split|mpmc|simple [OPTIONS]
+    bench all [OPTIONS]
+    litmus SB|MP|LB|IRIW|2+2W|all [--orderings relaxed|release/acquire|seqcst] [--iterations N]
+
+bench lock: every thread locks, increments a counter and unlocks, as often as it can.
+    spin is SpinLock, flag is SpinLockFlag, ticket is TicketLock, mcs is McsLock, clh is ClhLock,
+    futex is sync::Mutex and std is std::sync::Mutex.
+bench queue-locks: bench lock for flag, ticket, mcs and clh, contended, on 2, 8 and 32 threads.
+bench channel: every thread sends a message through a new channel and receives it.
+    oneshot is OneshotChannel, split is the Sender and Receiver from channel().
+    mpmc: every thread sends a message, then receives one.
+    simple: every thread sends a message through a SimpleChannel, then receives one.
+bench all: every lock and channel, in both scenarios.
+litmus: runs the litmus test and prints how often every outcome was observed.
+    fails when an outcome that the orderings forbid was observed.
+
+OPTIONS:
+    --threads N                        defaults to 4
+    --duration S, --warmup S           in seconds, default to 1 and 0.1
+    --scenario contended|uncontended   defaults to contended, see the bench module
+    --format text|csv|json             defaults to text
+--iterations defaults to 1000000.";
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+    run(&args).unwrap_or_else(|message| {
+        eprintln!("{message}\n\n{USAGE}");
+        ExitCode::FAILURE
+    })
+}
+
+fn run(args: &[String]) -> Result<ExitCode, String> {
+    let (command, args) = args.split_first().ok_or("missing command")?;
+    let options = Options::parse(args)?;
+    let bench = Bench::new()
+        .threads(options.threads)
+        .warmup(options.warmup)
+        .duration(options.duration);
+    let scenario = options.scenario;
+
+    let reports = match (command.as_str(), options.positional.as_deref()) {
+        ("bench", Some("lock")) => vec![match options.kind()? {
+            "spin" => bench::spin_lock(&bench, scenario),
+            "flag" => bench::lock::<SpinLockFlag>(&bench, scenario),
+            "ticket" => bench::lock::<TicketLock<u64>>(&bench, scenario),
+            "mcs" => bench::lock::<McsLock<u64>>(&bench, scenario),
+            "clh" => bench::lock::<ClhLock<u64>>(&bench, scenario),
+            "futex" => bench::lock::<sync::Mutex<u64>>(&bench, scenario),
+            "std" => bench::lock::<std::sync::Mutex<u64>>(&bench, scenario),
+            kind => return Err(format!("unknown lock kind {kind}")),
+        }],
+        ("bench", Some("channel")) => vec![match options.kind()? {
+            "oneshot" => bench::oneshot_channel(&bench, scenario),
+            "split" => bench::channel(&bench, scenario),
+            "mpmc" => bench::mpmc_channel(&bench, scenario),
+            "simple" => bench::simple_channel(&bench, scenario),
+            kind => return Err(format!("unknown channel kind {kind}")),
+        }],
+        ("bench", Some("queue-locks")) => bench::queue_locks(&bench),
+        ("bench", Some("all")) => bench_all(&bench),
+        ("bench", _) => {
+            return Err(String::from(
+                "bench needs lock, channel, queue-locks or all",
+            ))
+        }
+        ("litmus", Some(shape)) if shape == "all" || Shape::from_name(shape).is_some() => {
+            return Ok(litmus(shape, &options))
+        }
+        ("litmus", Some(shape)) => return Err(format!("unknown shape {shape}")),
+        ("litmus", None) => return Err(String::from("litmus needs a shape")),
+        (command, _) => return Err(format!("unknown command {command}")),
+    };
+
+    print_reports(&reports, &options.format);
+    Ok(ExitCode::SUCCESS)
+}
+
+fn bench_all(bench: &Bench) -> Vec<Report> {
+    let mut reports = Vec::new();
+    for scenario in Scenario::ALL {
+        reports.extend([
+            bench::simple_channel(bench, scenario),
+            bench::spin_lock(bench, scenario),
+            bench::lock::<sync::Mutex<u64>>(bench, scenario),
+            bench::lock::<std::sync::Mutex<u64>>(bench, scenario),
+            bench::lock::<SpinLockFlag>(bench, scenario),
+            bench::lock::<TicketLock<u64>>(bench, scenario),
+            bench::lock::<McsLock<u64>>(bench, scenario),
+            bench::lock::<ClhLock<u64>>(bench, scenario),
+            bench::oneshot_channel(bench, scenario),
+            bench::channel(bench, scenario),
+            bench::mpmc_channel(bench, scenario),
+        ]);
+    }
+    reports
+}
+
+fn print_reports(reports: &[Report], format: &str) {
+    match format {
+        "text" => reports.iter().for_each(|report| println!("{report}")),
+        "csv" => {
+            println!("{}", Report::CSV_HEADER);
+            reports
+                .iter()
+                .for_each(|report| println!("{}", report.to_csv()));
+        }
+        "json" => {
+            let reports: Vec<String> = reports.iter().map(Report::to_json).collect();
+            println!("[{}]", reports.join(",\n"));
+        }
+        _ => unreachable!("Options::parse checks the format"),
+    }
+}
+
+/// The command line after the command: at most one positional argument, and `--name value` pairs.
+struct Options {
+    positional: Option<String>,
+    kind: Option<String>,
+    threads: usize,
+    warmup: Duration,
+    duration: Duration,
+    scenario: Scenario,
+    format: String,
+    orderings: Option<Orderings>,
+    iterations: usize,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = Self {
+            positional: None,
+            kind: None,
+            threads: 4,
+            warmup: Duration::from_millis(100),
+            duration: Duration::from_secs(1),
+            scenario: Scenario::Contended,
+            format: String::from("text"),
+            orderings: None,
+            iterations: 1_000_000,
+        };
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let Some(name) = arg.strip_prefix("--") else {
+                if options.positional.replace(arg.clone()).is_some() {
+                    return Err(format!("unexpected argument {arg}"));
+                }
+                continue;
+            };
+            let value = args.next().ok_or(format!("--{name} needs a value"))?;
+            let invalid = || format!("invalid value for --{name}: {value}");
+            match name {
+                "kind" => options.kind = Some(value.clone()),
+                "threads" => {
+                    options.threads = value.parse().ok().filter(|&n| n > 0).ok_or_else(invalid)?
+                }
+                "warmup" => options.warmup = seconds(value).ok_or_else(invalid)?,
+                "duration" => options.duration = seconds(value).ok_or_else(invalid)?,
+                "scenario" => options.scenario = Scenario::from_name(value).ok_or_else(invalid)?,
+                "format" => {
+                    if !["text", "csv", "json"].contains(&value.as_str()) {
+                        return Err(invalid());
+                    }
+                    options.format = value.clone()
+                }
+                "orderings" => {
+                    options.orderings = Some(Orderings::from_name(value).ok_or_else(invalid)?)
+                }
+                "iterations" => options.iterations = value.parse().map_err(|_| invalid())?,
+                _ => return Err(format!("unknown option --{name}")),
+            }
+        }
+        Ok(options)
+    }
+
+    fn kind(&self) -> Result<&str, String> {
+        self.kind.as_deref().ok_or(String::from("missing --kind"))
+    }
+}
+
+fn litmus(shape: &str, options: &Options) -> ExitCode {
+    let shapes = match Shape::from_name(shape) {
+        Some(shape) => vec![shape],
+        None => Shape::ALL.to_vec(),
+    };
+    let orderings = match options.orderings {
+        Some(orderings) => vec![orderings],
+        None => Orderings::ALL.to_vec(),
+    };
+
+    let mut forbidden = 0;
+    for shape in shapes {
+        for &orderings in &orderings {
+            let histogram = litmus::run(shape, orderings, options.iterations);
+            println!("{histogram}");
+            forbidden += histogram.forbidden_count();
+        }
+    }
+
+    match forbidden {
+        0 => ExitCode::SUCCESS,
+        _ => {
+            eprintln!("observed forbidden outcomes {forbidden} times");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn seconds(value: &str) -> Option<Duration> {
+    Duration::try_from_secs_f64(value.parse().ok()?).ok()
+}
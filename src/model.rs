@@ -0,0 +1,1161 @@
+//! A minimal model checker in the spirit of the loom crate. Only compiled with the `model` feature.
+//! - With the feature enabled, the crate's prelude uses [atomic], [cell::UnsafeCell], [thread] and [hint]
+//!   from this module instead of the ones from std, so every lock and channel in the crate is instrumented.
+//! - [model] runs a closure over and over, exploring a different interleaving of its threads every time,
+//!   until every interleaving has been tried (up to a number of preemptions, see [Builder::preemption_bound]).
+//! - A load doesn't always see the latest store: every store that the memory model allows it to see is tried.
+//!   Happens-before relations are tracked with vector clocks, so a missing Acquire or Release shows up as a stale read.
+//! - Every access to an [cell::UnsafeCell] is checked against the accesses of other threads.
+//!   Two accesses without a happens-before relation, at least one of them a write, are reported as a data race.
+//! - Threads are real threads, but only one of them runs at a time. Every atomic operation is a point where
+//!   the scheduler can switch to another thread.
+//!
+//! Limitations, compared to what the hardware and the C++ memory model allow:
+//! - A load only reads stores that already happened, so load buffering (reading a value "from the future") is never explored.
+//! - SeqCst loads always read the latest store, and SeqCst fences synchronize with each other.
+//! - compare_exchange_weak never fails spuriously, and park never returns spuriously.
+//! - Timeouts aren't modeled: [thread::park_timeout] and [thread::sleep] yield instead of sleeping.
+//! - Only threads started with [thread::spawn] are modeled. Values shared between executions
+//!   (statics, or anything created outside of [model]) start over from their initial value in every execution.
+//! - Types from std aren't instrumented. Dropping the last [std::sync::Arc] doesn't happen-after anything as far as the model knows,
+//!   so share an [cell::UnsafeCell] between threads with [crate::ch6::Arc] instead.
+
+use std::{
+    any::Any,
+    cell::RefCell,
+    fmt,
+    panic::{self, AssertUnwindSafe, Location},
+    sync::{atomic::AtomicU64 as StdAtomicU64, Arc, Condvar as StdCondvar, Mutex as StdMutex},
+    thread as std_thread,
+};
+
+use super::*;
+
+pub mod atomic;
+pub mod cell;
+pub mod hint;
+pub mod thread;
+
+/// Explores every execution of `f` with the default [Builder].
+/// # Panics
+/// - When any execution panics, deadlocks, never finishes or has a data race.
+///   The panic of the failing execution is passed on.
+pub fn model<F>(f: F)
+where
+    F: Fn() + Send + Sync + 'static,
+{
+    Builder::new().check(f);
+}
+
+/// Configures how much of the state space [Builder::check] explores.
+#[derive(Debug, Clone, Copy)]
+pub struct Builder {
+    preemption_bound: Option<usize>,
+    max_steps: usize,
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Builder {
+    pub const fn new() -> Self {
+        Self {
+            preemption_bound: Some(2),
+            max_steps: 100_000,
+        }
+    }
+
+    /// The most times per execution the scheduler switches away from a thread that could have continued.
+    /// Most concurrency bugs need very few preemptions to show up, and every extra preemption multiplies the number of executions.
+    /// `None` explores every interleaving.
+    pub const fn preemption_bound(self, preemption_bound: Option<usize>) -> Self {
+        Self {
+            preemption_bound,
+            ..self
+        }
+    }
+
+    /// The most atomic operations a single execution may do before it is reported as a livelock.
+    pub const fn max_steps(self, max_steps: usize) -> Self {
+        Self { max_steps, ..self }
+    }
+
+    /// Runs `f` once for every execution, and returns how many executions there were.
+    /// # Panics
+    /// - When any execution panics, deadlocks, never finishes or has a data race.
+    ///   The panic of the failing execution is passed on.
+    pub fn check<F>(&self, f: F) -> usize
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        let f = Arc::new(f);
+        let mut path = Path::default();
+        let mut executions = 0;
+
+        loop {
+            executions += 1;
+
+            let execution = Arc::new(Execution::new(path, self));
+            let f = Arc::clone(&f);
+            execution.start_thread(0, Box::new(move || f()));
+            let (finished_path, failure) = execution.wait_until_finished();
+
+            if let Some(failure) = failure {
+                let message = failure
+                    .downcast_ref::<String>()
+                    .map(String::as_str)
+                    .or_else(|| failure.downcast_ref::<&str>().copied())
+                    .unwrap_or("Box<dyn Any>");
+                eprintln!("model: execution {executions} failed: {message}\nits choices were {finished_path}");
+                panic::resume_unwind(failure);
+            }
+
+            path = finished_path;
+            if !path.next() {
+                return executions;
+            }
+        }
+    }
+}
+
+/// The choices (which thread runs next, which store a load reads) made during an execution.
+/// The next execution replays them, except for the last one that still has an untried option.
+#[derive(Default)]
+struct Path {
+    branches: Vec<Branch>,
+    position: usize,
+}
+
+struct Branch {
+    chosen: usize,
+    options: usize,
+}
+
+impl Path {
+    /// Returns which of `options` options to take.
+    fn choose(&mut self, options: usize) -> usize {
+        // nothing to explore
+        if options <= 1 {
+            return 0;
+        }
+
+        let chosen = match self.branches.get(self.position) {
+            Some(branch) => {
+                assert_eq!(
+                    branch.options, options,
+                    "the model is not deterministic: a replayed execution had different options"
+                );
+                branch.chosen
+            }
+            None => {
+                self.branches.push(Branch { chosen: 0, options });
+                0
+            }
+        };
+        self.position += 1;
+
+        chosen
+    }
+
+    /// Moves on to the next untried execution. Returns `false` when every execution has been tried.
+    fn next(&mut self) -> bool {
+        self.position = 0;
+        while let Some(branch) = self.branches.last_mut() {
+            if branch.chosen + 1 < branch.options {
+                branch.chosen += 1;
+                return true;
+            }
+            self.branches.pop();
+        }
+        false
+    }
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let choices: Vec<String> = self
+            .branches
+            .iter()
+            .map(|branch| format!("{}/{}", branch.chosen, branch.options))
+            .collect();
+        write!(f, "[{}]", choices.join(", "))
+    }
+}
+
+/// A vector clock: for every thread, the last event of that thread that happened-before.
+#[derive(Debug, Clone, Default)]
+struct VersionVec(Vec<usize>);
+
+/// A single event of a thread.
+#[derive(Debug, Clone, Copy)]
+struct Epoch {
+    thread: usize,
+    time: usize,
+}
+
+impl VersionVec {
+    fn get(&self, thread: usize) -> usize {
+        self.0.get(thread).copied().unwrap_or(0)
+    }
+
+    /// Starts a new event of `thread`.
+    fn tick(&mut self, thread: usize) -> Epoch {
+        if self.0.len() <= thread {
+            self.0.resize(thread + 1, 0);
+        }
+        self.0[thread] += 1;
+        Epoch {
+            thread,
+            time: self.0[thread],
+        }
+    }
+
+    fn join(&mut self, other: &Self) {
+        if self.0.len() < other.0.len() {
+            self.0.resize(other.0.len(), 0);
+        }
+        for (time, &other_time) in self.0.iter_mut().zip(&other.0) {
+            *time = (*time).max(other_time);
+        }
+    }
+
+    /// Whether `epoch` happened-before the thread this clock belongs to.
+    fn contains(&self, epoch: Epoch) -> bool {
+        epoch.time <= self.get(epoch.thread)
+    }
+}
+
+/// Which execution and which thread of it the current thread is.
+#[derive(Clone)]
+struct Handle {
+    execution: Arc<Execution>,
+    id: usize,
+}
+
+std::thread_local! {
+    static CURRENT: RefCell<Option<Handle>> = const { RefCell::new(None) };
+}
+
+/// `None` when the current thread is not part of a [model] execution.
+fn current() -> Option<Handle> {
+    CURRENT
+        .try_with(|current| current.borrow().clone())
+        .ok()
+        .flatten()
+}
+
+/// Unwinds the threads of a failed execution, after the failure has been recorded.
+struct Abort;
+
+/// Where the instrumented types find their object in the current execution.
+/// Objects are created lazily, on the first operation of every execution.
+#[derive(Debug, Default)]
+struct ObjectKey(StdAtomicU64);
+
+impl ObjectKey {
+    const fn new() -> Self {
+        Self(StdAtomicU64::new(0))
+    }
+
+    /// Forgets the object, so the next operation creates a new one.
+    fn reset(&mut self) {
+        *self.0.get_mut() = 0;
+    }
+}
+
+static GENERATION: StdAtomicU64 = StdAtomicU64::new(0);
+
+struct Execution {
+    state: StdMutex<State>,
+    /// Notified when [State::active] changes.
+    turn: StdCondvar,
+    os_threads: StdMutex<Vec<std_thread::JoinHandle<()>>>,
+}
+
+struct State {
+    /// Tells objects of this execution apart from objects of older executions.
+    generation: u64,
+    path: Path,
+    preemption_bound: Option<usize>,
+    preemptions: usize,
+    max_steps: usize,
+    steps: usize,
+    threads: Vec<ThreadState>,
+    /// The only thread allowed to run.
+    active: usize,
+    objects: Vec<Object>,
+    /// Joined by every SeqCst fence, which makes SeqCst fences totally ordered.
+    seq_cst_clock: VersionVec,
+    next_yield_stamp: usize,
+    failure: Option<Box<dyn Any + Send>>,
+    /// Set once the execution failed. Nothing is explored anymore, every thread unwinds one after the other.
+    is_aborted: bool,
+}
+
+struct ThreadState {
+    status: Status,
+    clock: VersionVec,
+    /// The clock of the last Release fence, released by every later store.
+    release_fence_clock: VersionVec,
+    /// The clocks of the stores read by non-Acquire loads, acquired by the next Acquire fence.
+    pending_acquire_clock: VersionVec,
+    has_unpark_token: bool,
+    /// The clocks of the threads that unparked this one, acquired when [thread::park] returns.
+    unpark_clock: VersionVec,
+    /// Set while the thread waits for other threads to make progress. Lower stamps have been waiting longer.
+    yield_stamp: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Runnable,
+    Parked,
+    /// Waiting in [futex_wait] on this address.
+    Futex(usize),
+    /// Waiting for this thread to finish.
+    Joining(usize),
+    Finished,
+}
+
+enum Object {
+    Atomic(AtomicObject),
+    Cell(CellObject),
+}
+
+/// Every store to an atomic, in modification order.
+struct AtomicObject {
+    stores: Vec<Store>,
+    /// For every thread, the oldest store it may still read. Reads never go back in modification order.
+    oldest_readable: Vec<usize>,
+}
+
+struct Store {
+    value: u64,
+    epoch: Epoch,
+    /// What an acquire-load reading this store synchronizes with.
+    clock: VersionVec,
+}
+
+struct CellObject {
+    last_write: Option<Access>,
+    /// The reads since the last write, the latest one of every thread.
+    reads: Vec<Option<Access>>,
+}
+
+#[derive(Clone, Copy)]
+struct Access {
+    epoch: Epoch,
+    location: &'static Location<'static>,
+}
+
+impl ThreadState {
+    fn new(clock: VersionVec) -> Self {
+        Self {
+            status: Status::Runnable,
+            clock,
+            release_fence_clock: VersionVec::default(),
+            pending_acquire_clock: VersionVec::default(),
+            has_unpark_token: false,
+            unpark_clock: VersionVec::default(),
+            yield_stamp: None,
+        }
+    }
+}
+
+const fn is_acquire(order: Ordering) -> bool {
+    matches!(order, Acquire | AcqRel | SeqCst)
+}
+
+const fn is_release(order: Ordering) -> bool {
+    matches!(order, Release | AcqRel | SeqCst)
+}
+
+impl Execution {
+    fn new(path: Path, builder: &Builder) -> Self {
+        Self {
+            state: StdMutex::new(State {
+                generation: GENERATION.fetch_add(1, Relaxed) + 1,
+                path,
+                preemption_bound: builder.preemption_bound,
+                preemptions: 0,
+                max_steps: builder.max_steps,
+                steps: 0,
+                threads: vec![ThreadState::new(VersionVec::default())],
+                active: 0,
+                objects: Vec::new(),
+                seq_cst_clock: VersionVec::default(),
+                next_yield_stamp: 0,
+                failure: None,
+                is_aborted: false,
+            }),
+            turn: StdCondvar::new(),
+            os_threads: StdMutex::new(Vec::new()),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        // a panic while holding the lock is recorded as the failure of the execution, the state is still usable
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Runs `f` as thread `id` of this execution on a new OS thread, once it is its turn.
+    fn start_thread(self: &Arc<Self>, id: usize, f: Box<dyn FnOnce() + Send>) {
+        let execution = Arc::clone(self);
+        let os_thread = std_thread::spawn(move || {
+            CURRENT.with(|current| {
+                *current.borrow_mut() = Some(Handle {
+                    execution: Arc::clone(&execution),
+                    id,
+                })
+            });
+
+            let mut state = execution.lock();
+            while state.active != id {
+                state = execution
+                    .turn
+                    .wait(state)
+                    .unwrap_or_else(PoisonError::into_inner);
+            }
+            let is_aborted = state.is_aborted;
+            drop(state);
+
+            let result = match is_aborted {
+                true => Ok(()),
+                false => panic::catch_unwind(AssertUnwindSafe(f)),
+            };
+            execution.finish_thread(id, result);
+
+            CURRENT.with(|current| current.borrow_mut().take());
+        });
+
+        self.os_threads
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(os_thread);
+    }
+
+    fn finish_thread(&self, me: usize, result: std_thread::Result<()>) {
+        let mut state = self.lock();
+
+        if let Err(payload) = result {
+            if !payload.is::<Abort>() {
+                state.fail(payload);
+            }
+        }
+
+        state.threads[me].status = Status::Finished;
+        state.threads[me].clock.tick(me);
+        for thread in &mut state.threads {
+            if thread.status == Status::Joining(me) {
+                thread.status = Status::Runnable;
+            }
+        }
+        state.progress(me);
+
+        let next = match state.is_aborted {
+            true => None,
+            false => state.schedule(me),
+        };
+        match next {
+            Some(next) => state.active = next,
+            None => {
+                let is_finished = state.is_finished();
+                if !is_finished && !state.is_aborted {
+                    state.fail(Box::new(String::from(
+                        "model: deadlock, every thread that hasn't finished is blocked",
+                    )));
+                }
+                // let the remaining threads unwind one after the other
+                if let Some(next) = state
+                    .threads
+                    .iter()
+                    .position(|t| t.status != Status::Finished)
+                {
+                    state.active = next;
+                }
+            }
+        }
+
+        self.turn.notify_all();
+    }
+
+    fn wait_until_finished(&self) -> (Path, Option<Box<dyn Any + Send>>) {
+        let mut state = self.lock();
+        while !state.is_finished() {
+            state = self
+                .turn
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        let path = std::mem::take(&mut state.path);
+        let failure = state.failure.take();
+        drop(state);
+
+        let os_threads = std::mem::take(
+            &mut *self
+                .os_threads
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+        );
+        for os_thread in os_threads {
+            // model threads catch their own panics
+            os_thread.join().expect("model threads don't panic");
+        }
+
+        (path, failure)
+    }
+
+    /// Lets the scheduler pick which thread does the next operation, and waits until it is `me`'s turn again.
+    fn yield_point(&self, me: usize) -> std::sync::MutexGuard<'_, State> {
+        let mut state = self.lock();
+        if state.is_aborted {
+            return state;
+        }
+
+        state = self.step(state);
+        let next = state.schedule(me);
+        self.switch_to(state, me, next)
+    }
+
+    /// Makes `me` wait until the other threads made progress (see [State::yield_now]).
+    fn yield_now(&self, me: usize) {
+        let mut state = self.lock();
+        if state.is_aborted {
+            return;
+        }
+
+        state = self.step(state);
+        state.yield_now(me);
+        let next = state.schedule(me);
+        drop(self.switch_to(state, me, next));
+    }
+
+    /// Counts a step, and fails the execution when it did too many.
+    fn step<'a>(
+        &'a self,
+        mut state: std::sync::MutexGuard<'a, State>,
+    ) -> std::sync::MutexGuard<'a, State> {
+        state.steps += 1;
+        if state.steps > state.max_steps {
+            let message = format!(
+                "model: an execution did more than {} steps. Is a thread spinning without hint::spin_loop or thread::yield_now?",
+                state.max_steps
+            );
+            state.fail(Box::new(message));
+            drop(state);
+            panic::resume_unwind(Box::new(Abort));
+        }
+        state
+    }
+
+    /// Gives the turn to `next` (or fails the execution when nobody can run), and waits until it is `me`'s turn again.
+    fn switch_to<'a>(
+        &'a self,
+        mut state: std::sync::MutexGuard<'a, State>,
+        me: usize,
+        next: Option<usize>,
+    ) -> std::sync::MutexGuard<'a, State> {
+        match next {
+            Some(next) => state.active = next,
+            None => {
+                state.fail(Box::new(String::from(
+                    "model: deadlock, every thread that hasn't finished is blocked",
+                )));
+                state.threads[me].status = Status::Runnable;
+            }
+        }
+
+        if state.active != me {
+            self.turn.notify_all();
+            while state.active != me {
+                state = self
+                    .turn
+                    .wait(state)
+                    .unwrap_or_else(PoisonError::into_inner);
+            }
+        }
+
+        // unwinding runs destructors, those keep running (without exploring anything) instead of panicking again
+        if state.is_aborted && !std_thread::panicking() {
+            drop(state);
+            panic::resume_unwind(Box::new(Abort));
+        }
+
+        state
+    }
+
+    /// Blocks `me` with `status` until another thread makes it runnable again.
+    fn block<'a>(
+        &'a self,
+        mut state: std::sync::MutexGuard<'a, State>,
+        me: usize,
+        status: Status,
+    ) -> std::sync::MutexGuard<'a, State> {
+        if state.is_aborted {
+            return state;
+        }
+        state.threads[me].status = status;
+        let next = state.schedule(me);
+        self.switch_to(state, me, next)
+    }
+}
+
+impl State {
+    fn is_finished(&self) -> bool {
+        self.threads.iter().all(|t| t.status == Status::Finished)
+    }
+
+    fn fail(&mut self, payload: Box<dyn Any + Send>) {
+        if self.failure.is_none() {
+            self.failure = Some(payload);
+        }
+        self.is_aborted = true;
+    }
+
+    fn choose(&mut self, options: usize) -> usize {
+        match self.is_aborted {
+            true => 0,
+            false => self.path.choose(options),
+        }
+    }
+
+    /// Picks the thread that runs next. `None` when every thread is blocked or finished.
+    fn schedule(&mut self, me: usize) -> Option<usize> {
+        let can_run = |thread: &ThreadState| {
+            thread.status == Status::Runnable && thread.yield_stamp.is_none()
+        };
+
+        let can_continue = can_run(&self.threads[me]);
+        let may_preempt = self
+            .preemption_bound
+            .is_none_or(|bound| self.preemptions < bound);
+
+        let mut options = Vec::new();
+        if can_continue {
+            options.push(me);
+        }
+        if !can_continue || may_preempt {
+            options
+                .extend((0..self.threads.len()).filter(|&t| t != me && can_run(&self.threads[t])));
+        }
+
+        let next = if options.is_empty() {
+            // every runnable thread is waiting for the others. run the one that has been waiting the longest
+            (0..self.threads.len())
+                .filter(|&t| self.threads[t].status == Status::Runnable)
+                .min_by_key(|&t| self.threads[t].yield_stamp)?
+        } else {
+            let next = options[self.choose(options.len())];
+            if can_continue && next != me {
+                self.preemptions += 1;
+            }
+            next
+        };
+
+        self.threads[next].yield_stamp = None;
+        Some(next)
+    }
+
+    /// `me` changed something other threads might be waiting for, so threads that yielded get a turn again.
+    fn progress(&mut self, me: usize) {
+        for (t, thread) in self.threads.iter_mut().enumerate() {
+            if t != me {
+                thread.yield_stamp = None;
+            }
+        }
+    }
+
+    /// Makes `me` wait until other threads made progress, and lets it see the latest value of every atomic afterwards.
+    fn yield_now(&mut self, me: usize) {
+        self.threads[me].yield_stamp = Some(self.next_yield_stamp);
+        self.next_yield_stamp += 1;
+
+        for object in &mut self.objects {
+            if let Object::Atomic(atomic) = object {
+                let latest = atomic.stores.len() - 1;
+                *atomic.oldest_readable_mut(me) = latest;
+            }
+        }
+    }
+
+    fn object(&mut self, key: &ObjectKey, new: impl FnOnce(&Self) -> Object) -> usize {
+        let key_value = key.0.load(Relaxed);
+        if key_value >> 32 == self.generation && key_value as u32 != 0 {
+            return (key_value as u32 - 1) as usize;
+        }
+
+        let index = self.objects.len();
+        self.objects.push(new(self));
+        key.0
+            .store(self.generation << 32 | (index as u64 + 1), Relaxed);
+        index
+    }
+
+    fn atomic(&mut self, key: &ObjectKey, me: usize, initial: impl FnOnce() -> u64) -> usize {
+        self.object(key, |state| {
+            Object::Atomic(AtomicObject {
+                stores: vec![Store {
+                    value: initial(),
+                    epoch: Epoch {
+                        thread: me,
+                        time: state.threads[me].clock.get(me),
+                    },
+                    clock: VersionVec::default(),
+                }],
+                oldest_readable: Vec::new(),
+            })
+        })
+    }
+
+    fn atomic_mut(&mut self, index: usize) -> &mut AtomicObject {
+        match &mut self.objects[index] {
+            Object::Atomic(atomic) => atomic,
+            Object::Cell(_) => {
+                unreachable!("object keys are only shared by objects of the same kind")
+            }
+        }
+    }
+
+    /// What a store by `me` with `order` releases.
+    fn release_clock(&self, me: usize, order: Ordering) -> VersionVec {
+        let thread = &self.threads[me];
+        match is_release(order) {
+            true => thread.clock.clone(),
+            false => thread.release_fence_clock.clone(),
+        }
+    }
+
+    fn acquire(&mut self, me: usize, clock: &VersionVec, order: Ordering) {
+        let thread = &mut self.threads[me];
+        match is_acquire(order) {
+            true => thread.clock.join(clock),
+            false => thread.pending_acquire_clock.join(clock),
+        }
+    }
+
+    fn load(&mut self, me: usize, index: usize, order: Ordering) -> u64 {
+        self.threads[me].clock.tick(me);
+
+        let clock = &self.threads[me].clock;
+        let atomic = match &self.objects[index] {
+            Object::Atomic(atomic) => atomic,
+            Object::Cell(_) => {
+                unreachable!("object keys are only shared by objects of the same kind")
+            }
+        };
+        let latest = atomic.stores.len() - 1;
+        // can't read a store older than one that happened-before this load
+        let oldest = atomic
+            .stores
+            .iter()
+            .rposition(|store| clock.contains(store.epoch))
+            .unwrap_or(0)
+            .max(atomic.oldest_readable(me));
+
+        let read = match order {
+            SeqCst => latest,
+            // the latest store first, so the first execution is the most intuitive one
+            _ => latest - self.choose(latest - oldest + 1),
+        };
+
+        let atomic = self.atomic_mut(index);
+        *atomic.oldest_readable_mut(me) = read;
+        let store = &atomic.stores[read];
+        let (value, store_clock) = (store.value, store.clock.clone());
+        self.acquire(me, &store_clock, order);
+
+        value
+    }
+
+    fn store(&mut self, me: usize, index: usize, value: u64, order: Ordering) {
+        let epoch = self.threads[me].clock.tick(me);
+        let clock = self.release_clock(me, order);
+
+        let atomic = self.atomic_mut(index);
+        let is_changed = atomic
+            .stores
+            .last()
+            .is_some_and(|latest| latest.value != value);
+        atomic.stores.push(Store {
+            value,
+            epoch,
+            clock,
+        });
+        *atomic.oldest_readable_mut(me) = atomic.stores.len() - 1;
+
+        if is_changed {
+            self.progress(me);
+        }
+    }
+
+    /// A read-modify-write always reads the latest store. `f` returns `None` to not write anything (a failed compare_exchange).
+    fn read_modify_write(
+        &mut self,
+        me: usize,
+        index: usize,
+        f: impl FnOnce(u64) -> Option<u64>,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<u64, u64> {
+        let epoch = self.threads[me].clock.tick(me);
+
+        let atomic = self.atomic_mut(index);
+        let latest = atomic.stores.len() - 1;
+        *atomic.oldest_readable_mut(me) = latest;
+        let previous = &atomic.stores[latest];
+        let (previous_value, previous_clock) = (previous.value, previous.clock.clone());
+
+        let Some(value) = f(previous_value) else {
+            self.acquire(me, &previous_clock, failure);
+            return Err(previous_value);
+        };
+
+        self.acquire(me, &previous_clock, success);
+        // a read-modify-write continues the release sequence of the store it read
+        let mut clock = self.release_clock(me, success);
+        clock.join(&previous_clock);
+
+        let atomic = self.atomic_mut(index);
+        atomic.stores.push(Store {
+            value,
+            epoch,
+            clock,
+        });
+        *atomic.oldest_readable_mut(me) = latest + 1;
+
+        if value != previous_value {
+            self.progress(me);
+        }
+
+        Ok(previous_value)
+    }
+
+    fn fence(&mut self, me: usize, order: Ordering) {
+        self.threads[me].clock.tick(me);
+
+        let thread = &mut self.threads[me];
+        if is_acquire(order) {
+            let pending = std::mem::take(&mut thread.pending_acquire_clock);
+            thread.clock.join(&pending);
+        }
+        if order == SeqCst {
+            thread.clock.join(&self.seq_cst_clock);
+            self.seq_cst_clock.join(&thread.clock);
+        }
+        if is_release(order) {
+            thread.release_fence_clock = thread.clock.clone();
+        }
+    }
+
+    fn latest(&mut self, index: usize) -> u64 {
+        let atomic = self.atomic_mut(index);
+        atomic
+            .stores
+            .last()
+            .expect("an atomic has at least one store")
+            .value
+    }
+
+    /// Checks an access to a [cell::UnsafeCell] by `me` against the previous accesses by other threads.
+    fn access_cell(
+        &mut self,
+        me: usize,
+        key: &ObjectKey,
+        is_write: bool,
+        location: &'static Location<'static>,
+    ) -> Result<(), String> {
+        let index = self.object(key, |_| {
+            Object::Cell(CellObject {
+                last_write: None,
+                reads: Vec::new(),
+            })
+        });
+        let epoch = self.threads[me].clock.tick(me);
+        let clock = &self.threads[me].clock;
+
+        let Object::Cell(cell) = &mut self.objects[index] else {
+            unreachable!("object keys are only shared by objects of the same kind")
+        };
+
+        let kind = match is_write {
+            true => "write",
+            false => "read",
+        };
+        let race = |other: &Access, other_kind: &str| {
+            format!(
+                "model: data race, {kind} by thread {me} at {location} is concurrent with {other_kind} by thread {} at {}",
+                other.epoch.thread, other.location
+            )
+        };
+
+        if let Some(write) = &cell.last_write {
+            if !clock.contains(write.epoch) {
+                return Err(race(write, "a write"));
+            }
+        }
+        if is_write {
+            if let Some(read) = cell
+                .reads
+                .iter()
+                .flatten()
+                .find(|read| !clock.contains(read.epoch))
+            {
+                return Err(race(read, "a read"));
+            }
+        }
+
+        let access = Access { epoch, location };
+        if is_write {
+            cell.last_write = Some(access);
+            cell.reads.clear();
+        } else {
+            if cell.reads.len() <= me {
+                cell.reads.resize(me + 1, None);
+            }
+            cell.reads[me] = Some(access);
+        }
+
+        Ok(())
+    }
+}
+
+impl AtomicObject {
+    fn oldest_readable(&self, thread: usize) -> usize {
+        self.oldest_readable.get(thread).copied().unwrap_or(0)
+    }
+
+    fn oldest_readable_mut(&mut self, thread: usize) -> &mut usize {
+        if self.oldest_readable.len() <= thread {
+            self.oldest_readable.resize(thread + 1, 0);
+        }
+        &mut self.oldest_readable[thread]
+    }
+}
+
+// The operations of the instrumented types. They all return `None` when the current thread isn't part of an execution,
+// in which case the instrumented type falls back to the std type it wraps.
+
+fn atomic_load(key: &ObjectKey, initial: impl FnOnce() -> u64, order: Ordering) -> Option<u64> {
+    let Handle { execution, id } = current()?;
+    let mut state = execution.yield_point(id);
+    let index = state.atomic(key, id, initial);
+    Some(state.load(id, index, order))
+}
+
+fn atomic_store(
+    key: &ObjectKey,
+    initial: impl FnOnce() -> u64,
+    value: u64,
+    order: Ordering,
+) -> Option<()> {
+    let Handle { execution, id } = current()?;
+    let mut state = execution.yield_point(id);
+    let index = state.atomic(key, id, initial);
+    state.store(id, index, value, order);
+    Some(())
+}
+
+fn atomic_read_modify_write(
+    key: &ObjectKey,
+    initial: impl FnOnce() -> u64,
+    f: impl FnOnce(u64) -> Option<u64>,
+    success: Ordering,
+    failure: Ordering,
+) -> Option<Result<u64, u64>> {
+    let Handle { execution, id } = current()?;
+    let mut state = execution.yield_point(id);
+    let index = state.atomic(key, id, initial);
+    Some(state.read_modify_write(id, index, f, success, failure))
+}
+
+/// The latest value, for `get_mut` and `into_inner` which have exclusive access. Not a yield point.
+fn atomic_latest(key: &ObjectKey, initial: impl FnOnce() -> u64) -> Option<u64> {
+    let Handle { execution, id } = current()?;
+    let mut state = execution.lock();
+    let index = state.atomic(key, id, initial);
+    Some(state.latest(index))
+}
+
+fn atomic_fence(order: Ordering) -> Option<()> {
+    let Handle { execution, id } = current()?;
+    let mut state = execution.yield_point(id);
+    state.fence(id, order);
+    Some(())
+}
+
+fn cell_access(key: &ObjectKey, is_write: bool, location: &'static Location<'static>) {
+    let Some(Handle { execution, id }) = current() else {
+        return;
+    };
+    let mut state = execution.lock();
+    if state.is_aborted {
+        return;
+    }
+    if let Err(race) = state.access_cell(id, key, is_write, location) {
+        // abort instead of panicking, so the destructors that run while unwinding don't report the race again
+        state.fail(Box::new(race));
+        drop(state);
+        panic::resume_unwind(Box::new(Abort));
+    }
+}
+
+/// Like [crate::atomic_wait::wait]: blocks until [futex_wake] if the latest value of `a` is still `expected`.
+/// Returns `None` when the current thread isn't part of an execution.
+pub(crate) fn futex_wait(a: &atomic::AtomicU32, expected: u32, has_timeout: bool) -> Option<()> {
+    let Handle { execution, id } = current()?;
+    let mut state = execution.yield_point(id);
+    let index = state.atomic(&a.object, id, || a.inner.load(Relaxed) as u64);
+
+    // the kernel checks the value and goes to sleep atomically
+    let atomic = state.atomic_mut(index);
+    let latest = atomic.stores.len() - 1;
+    *atomic.oldest_readable_mut(id) = latest;
+    if atomic.stores[latest].value != expected as u64 {
+        return Some(());
+    }
+
+    let address = a as *const atomic::AtomicU32 as usize;
+    match has_timeout {
+        // the timeout "expires" once the other threads are stuck
+        true => {
+            state.yield_now(id);
+            let next = state.schedule(id);
+            drop(execution.switch_to(state, id, next));
+        }
+        false => drop(execution.block(state, id, Status::Futex(address))),
+    }
+    Some(())
+}
+
+/// Like [crate::atomic_wait::wake_one] and [crate::atomic_wait::wake_all]. `a` is never dereferenced.
+pub(crate) fn futex_wake(a: *const atomic::AtomicU32, all: bool) -> Option<()> {
+    let Handle { execution, id } = current()?;
+    let mut state = execution.yield_point(id);
+    let address = a as usize;
+
+    let waiters: Vec<usize> = (0..state.threads.len())
+        .filter(|&t| state.threads[t].status == Status::Futex(address))
+        .collect();
+    if waiters.is_empty() {
+        return Some(());
+    }
+
+    let woken = match all {
+        true => waiters,
+        // the kernel wakes any one of them
+        false => vec![waiters[state.choose(waiters.len())]],
+    };
+    for t in woken {
+        state.threads[t].status = Status::Runnable;
+    }
+    state.progress(id);
+
+    Some(())
+}
+
+#[test]
+fn model_explores_every_interleaving() {
+    // two threads each doing two stores: 4 choose 2 interleavings
+    let order = Arc::new(std::sync::Mutex::new(std::collections::HashSet::new()));
+    let executions = Builder::new().preemption_bound(None).check({
+        let order = Arc::clone(&order);
+        move || {
+            let a = Arc::new(AtomicUsize::new(0));
+            let thread = thread::spawn({
+                let a = Arc::clone(&a);
+                move || {
+                    a.fetch_add(1, Relaxed);
+                    a.fetch_add(1, Relaxed);
+                }
+            });
+            a.fetch_add(10, Relaxed);
+            let first = a.fetch_add(10, Relaxed);
+            thread.join().unwrap();
+            order.lock().unwrap().insert(first);
+        }
+    });
+
+    // the second add of the main thread saw 10, 11 or 12
+    assert_eq!(*order.lock().unwrap(), [10, 11, 12].into());
+    assert!(executions >= 6);
+}
+
+#[test]
+fn model_relaxed_store_buffering() {
+    // with Relaxed (or Acquire/Release) both loads can read the initial value, with SeqCst they can't
+    for (order, expect_both_zero) in [(Relaxed, true), (SeqCst, false)] {
+        let outcomes = Arc::new(std::sync::Mutex::new(std::collections::HashSet::new()));
+        model({
+            let outcomes = Arc::clone(&outcomes);
+            move || {
+                let x = Arc::new(AtomicBool::new(false));
+                let y = Arc::new(AtomicBool::new(false));
+                let thread = thread::spawn({
+                    let (x, y) = (Arc::clone(&x), Arc::clone(&y));
+                    move || {
+                        y.store(true, order);
+                        x.load(order)
+                    }
+                });
+                x.store(true, order);
+                let saw_y = y.load(order);
+                let saw_x = thread.join().unwrap();
+                outcomes.lock().unwrap().insert((saw_x, saw_y));
+            }
+        });
+        assert_eq!(
+            outcomes.lock().unwrap().contains(&(false, false)),
+            expect_both_zero
+        );
+    }
+}
+
+#[test]
+#[should_panic(expected = "data race")]
+fn model_finds_missing_release() {
+    struct Shared {
+        data: UnsafeCell<i32>,
+        is_ready: AtomicBool,
+    }
+    unsafe impl Sync for Shared {}
+
+    model(|| {
+        let shared = Arc::new(Shared {
+            data: UnsafeCell::new(0),
+            is_ready: AtomicBool::new(false),
+        });
+        thread::spawn({
+            let shared = Arc::clone(&shared);
+            move || {
+                unsafe { *shared.data.get() = 1 };
+                // should be Release
+                shared.is_ready.store(true, Relaxed);
+            }
+        });
+        if shared.is_ready.load(Acquire) {
+            assert_eq!(unsafe { *shared.data.get() }, 1);
+        }
+    });
+}
+
+#[test]
+#[should_panic(expected = "deadlock")]
+fn model_finds_deadlock() {
+    model(|| {
+        let flag = Arc::new(AtomicBool::new(false));
+        let thread = thread::spawn({
+            let flag = Arc::clone(&flag);
+            move || {
+                if !flag.load(Acquire) {
+                    // nobody unparks this thread when the flag was set first
+                    thread::park();
+                }
+            }
+        });
+        flag.store(true, Release);
+        thread.join().unwrap();
+    });
+}
@@ -0,0 +1,276 @@
+//! Instrumented versions of the types in [std::sync::atomic], with the same API.
+//! Outside of a [super::model] execution they behave exactly like the std type they wrap.
+//!
+//! Every type is `repr(C)` with the std atomic as its first field, so the address of the atomic is the address of the std atomic.
+//! The futex syscalls get the address of the std atomic from `as_ptr`, and wake up threads by the address of the atomic.
+
+use std::sync::atomic as std_atomic;
+
+pub use std::sync::atomic::{compiler_fence, Ordering};
+
+use super::*;
+
+/// A value that fits in the `u64` the model stores for every atomic.
+trait Value: Copy {
+    fn to_u64(self) -> u64;
+    fn from_u64(value: u64) -> Self;
+}
+
+macro_rules! value {
+    ($($int:ty),*) => {$(
+        impl Value for $int {
+            fn to_u64(self) -> u64 {
+                self as u64
+            }
+            fn from_u64(value: u64) -> Self {
+                value as $int
+            }
+        }
+    )*};
+}
+value!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+impl Value for bool {
+    fn to_u64(self) -> u64 {
+        self as u64
+    }
+    fn from_u64(value: u64) -> Self {
+        value != 0
+    }
+}
+
+impl<T> Value for *mut T {
+    fn to_u64(self) -> u64 {
+        self as usize as u64
+    }
+    fn from_u64(value: u64) -> Self {
+        value as usize as *mut T
+    }
+}
+
+/// The operations every atomic type has, on top of [super::ObjectKey] and the std atomic it wraps.
+macro_rules! atomic_common {
+    ($value:ty) => {
+        fn initial(&self) -> impl FnOnce() -> u64 + '_ {
+            || self.inner.load(Relaxed).to_u64()
+        }
+
+        pub fn load(&self, order: Ordering) -> $value {
+            match atomic_load(&self.object, self.initial(), order) {
+                Some(value) => <$value>::from_u64(value),
+                None => self.inner.load(order),
+            }
+        }
+
+        pub fn store(&self, value: $value, order: Ordering) {
+            if atomic_store(&self.object, self.initial(), value.to_u64(), order).is_none() {
+                self.inner.store(value, order);
+            }
+        }
+
+        pub fn swap(&self, value: $value, order: Ordering) -> $value {
+            match atomic_read_modify_write(
+                &self.object,
+                self.initial(),
+                |_| Some(value.to_u64()),
+                order,
+                order,
+            ) {
+                Some(previous) => <$value>::from_u64(previous.unwrap_or_else(|x| x)),
+                None => self.inner.swap(value, order),
+            }
+        }
+
+        pub fn compare_exchange(
+            &self,
+            current: $value,
+            new: $value,
+            success: Ordering,
+            failure: Ordering,
+        ) -> Result<$value, $value> {
+            match atomic_read_modify_write(
+                &self.object,
+                self.initial(),
+                |value| (value == current.to_u64()).then_some(new.to_u64()),
+                success,
+                failure,
+            ) {
+                Some(result) => result.map(<$value>::from_u64).map_err(<$value>::from_u64),
+                None => self.inner.compare_exchange(current, new, success, failure),
+            }
+        }
+
+        /// Never fails spuriously in a model execution.
+        pub fn compare_exchange_weak(
+            &self,
+            current: $value,
+            new: $value,
+            success: Ordering,
+            failure: Ordering,
+        ) -> Result<$value, $value> {
+            match current_execution_is_modeled() {
+                true => self.compare_exchange(current, new, success, failure),
+                false => self
+                    .inner
+                    .compare_exchange_weak(current, new, success, failure),
+            }
+        }
+
+        pub fn fetch_update<F>(
+            &self,
+            set_order: Ordering,
+            fetch_order: Ordering,
+            mut f: F,
+        ) -> Result<$value, $value>
+        where
+            F: FnMut($value) -> Option<$value>,
+        {
+            let mut previous = self.load(fetch_order);
+            while let Some(next) = f(previous) {
+                match self.compare_exchange_weak(previous, next, set_order, fetch_order) {
+                    Ok(previous) => return Ok(previous),
+                    Err(actual) => previous = actual,
+                }
+            }
+            Err(previous)
+        }
+
+        /// Exclusive access, so this is not a yield point.
+        pub fn get_mut(&mut self) -> &mut $value {
+            if let Some(latest) = atomic_latest(&self.object, self.initial()) {
+                *self.inner.get_mut() = <$value>::from_u64(latest);
+                // the value might be changed through the reference, start over from it on the next operation
+                self.object.reset();
+            }
+            self.inner.get_mut()
+        }
+
+        pub fn into_inner(self) -> $value {
+            match atomic_latest(&self.object, self.initial()) {
+                Some(latest) => <$value>::from_u64(latest),
+                None => self.inner.into_inner(),
+            }
+        }
+
+        /// Points at the std atomic, which isn't updated during a model execution.
+        pub const fn as_ptr(&self) -> *mut $value {
+            self.inner.as_ptr()
+        }
+    };
+}
+
+/// A read-modify-write method that combines the old value with an argument.
+macro_rules! atomic_fetch {
+    ($value:ty, $($name:ident => $f:expr,)*) => {$(
+        pub fn $name(&self, argument: $value, order: Ordering) -> $value {
+            let f: fn($value, $value) -> $value = $f;
+            match atomic_read_modify_write(
+                &self.object,
+                self.initial(),
+                |value| Some(f(<$value>::from_u64(value), argument).to_u64()),
+                order,
+                order,
+            ) {
+                Some(previous) => <$value>::from_u64(previous.unwrap_or_else(|x| x)),
+                None => self.inner.$name(argument, order),
+            }
+        }
+    )*};
+}
+
+macro_rules! atomic_int {
+    ($($name:ident($int:ty)),*) => {$(
+        #[derive(Debug, Default)]
+        #[repr(C)]
+        pub struct $name {
+            pub(super) inner: std_atomic::$name,
+            pub(super) object: ObjectKey,
+        }
+
+        impl $name {
+            pub const fn new(value: $int) -> Self {
+                Self {
+                    inner: std_atomic::$name::new(value),
+                    object: ObjectKey::new(),
+                }
+            }
+
+            atomic_common!($int);
+            atomic_fetch!(
+                $int,
+                fetch_add => <$int>::wrapping_add,
+                fetch_sub => <$int>::wrapping_sub,
+                fetch_and => |a, b| a & b,
+                fetch_or => |a, b| a | b,
+                fetch_xor => |a, b| a ^ b,
+                fetch_max => <$int>::max,
+                fetch_min => <$int>::min,
+            );
+        }
+    )*};
+}
+atomic_int!(
+    AtomicU8(u8),
+    AtomicU16(u16),
+    AtomicU32(u32),
+    AtomicU64(u64),
+    AtomicUsize(usize),
+    AtomicI8(i8),
+    AtomicI16(i16),
+    AtomicI32(i32),
+    AtomicI64(i64),
+    AtomicIsize(isize)
+);
+
+#[derive(Debug, Default)]
+#[repr(C)]
+pub struct AtomicBool {
+    inner: std_atomic::AtomicBool,
+    object: ObjectKey,
+}
+
+impl AtomicBool {
+    pub const fn new(value: bool) -> Self {
+        Self {
+            inner: std_atomic::AtomicBool::new(value),
+            object: ObjectKey::new(),
+        }
+    }
+
+    atomic_common!(bool);
+    atomic_fetch!(
+        bool,
+        fetch_and => |a, b| a & b,
+        fetch_or => |a, b| a | b,
+        fetch_xor => |a, b| a ^ b,
+        fetch_nand => |a, b| !(a & b),
+    );
+}
+
+#[derive(Debug, Default)]
+#[repr(C)]
+pub struct AtomicPtr<T> {
+    inner: std_atomic::AtomicPtr<T>,
+    object: ObjectKey,
+}
+
+impl<T> AtomicPtr<T> {
+    pub const fn new(value: *mut T) -> Self {
+        Self {
+            inner: std_atomic::AtomicPtr::new(value),
+            object: ObjectKey::new(),
+        }
+    }
+
+    atomic_common!(*mut T);
+}
+
+pub fn fence(order: Ordering) {
+    if atomic_fence(order).is_none() {
+        std_atomic::fence(order);
+    }
+}
+
+fn current_execution_is_modeled() -> bool {
+    current().is_some()
+}
@@ -0,0 +1,58 @@
+//! An instrumented [std::cell::UnsafeCell] that reports data races.
+//! Outside of a [super::model] execution it behaves exactly like the std type it wraps.
+
+use std::{mem::ManuallyDrop, panic::Location};
+
+use super::*;
+
+/// Every [UnsafeCell::get] is recorded as a write, every [UnsafeCell::get_shared] as a read.
+/// The access happens when the pointer is created, so create it right where it is used.
+#[derive(Debug, Default)]
+pub struct UnsafeCell<T> {
+    value: std::cell::UnsafeCell<T>,
+    object: ObjectKey,
+}
+
+impl<T> UnsafeCell<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            value: std::cell::UnsafeCell::new(value),
+            object: ObjectKey::new(),
+        }
+    }
+
+    /// A pointer for writing (or reading and writing) the value.
+    #[track_caller]
+    pub fn get(&self) -> *mut T {
+        cell_access(&self.object, true, Location::caller());
+        self.value.get()
+    }
+
+    /// A pointer that is only read through. Reads by several threads at the same time aren't a data race.
+    #[track_caller]
+    pub fn get_shared(&self) -> *const T {
+        cell_access(&self.object, false, Location::caller());
+        self.value.get()
+    }
+
+    #[track_caller]
+    pub fn get_mut(&mut self) -> &mut T {
+        cell_access(&self.object, true, Location::caller());
+        self.value.get_mut()
+    }
+
+    #[track_caller]
+    pub fn into_inner(self) -> T {
+        let this = ManuallyDrop::new(self);
+        cell_access(&this.object, true, Location::caller());
+        // SAFETY: `this` is never used or dropped again
+        unsafe { ptr::read(&this.value) }.into_inner()
+    }
+}
+
+/// Dropping the value (and freeing its memory) has to happen-after every other access.
+impl<T> Drop for UnsafeCell<T> {
+    fn drop(&mut self) {
+        cell_access(&self.object, true, Location::caller());
+    }
+}
@@ -0,0 +1,9 @@
+//! An instrumented [std::hint::spin_loop].
+
+/// In a [super::model] execution this yields to the other threads, so a spin loop can't spin forever.
+pub fn spin_loop() {
+    match super::current() {
+        Some(_) => super::thread::yield_now(),
+        None => std::hint::spin_loop(),
+    }
+}
@@ -0,0 +1,213 @@
+//! Instrumented versions of the functions in [std::thread], with the same API.
+//! Outside of a [super::model] execution they behave exactly like the std functions.
+
+use std::{
+    fmt,
+    sync::{Arc, Mutex as StdMutex},
+    thread as std_thread,
+};
+
+pub use std::thread::{
+    available_parallelism, panicking, Result, Scope, ScopedJoinHandle, ThreadId,
+};
+
+use super::{Handle, State, Status};
+use crate::{Duration, PoisonError};
+
+/// A handle to a thread, which can unpark it.
+#[derive(Clone)]
+pub struct Thread {
+    inner: std_thread::Thread,
+    model: Option<Handle>,
+}
+
+impl fmt::Debug for Thread {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+impl Thread {
+    pub fn id(&self) -> ThreadId {
+        self.inner.id()
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.inner.name()
+    }
+
+    pub fn unpark(&self) {
+        let Some(target) = &self.model else {
+            return self.inner.unpark();
+        };
+
+        match super::current() {
+            Some(me) if Arc::ptr_eq(&me.execution, &target.execution) => {
+                let mut state = target.execution.yield_point(me.id);
+                // unpark synchronizes-with the park it wakes up, like std's
+                state.threads[me.id].clock.tick(me.id);
+                let clock = state.threads[me.id].clock.clone();
+                state.threads[target.id].unpark_clock.join(&clock);
+                unpark(&mut state, target.id);
+            }
+            // not a model thread. nothing to schedule, just wake the target
+            _ => unpark(&mut target.execution.lock(), target.id),
+        }
+    }
+}
+
+fn unpark(state: &mut State, target: usize) {
+    let thread = &mut state.threads[target];
+    match thread.status {
+        Status::Parked => thread.status = Status::Runnable,
+        _ => thread.has_unpark_token = true,
+    }
+    // progress for every thread, including the one that was parked
+    state.progress(usize::MAX);
+}
+
+pub fn current() -> Thread {
+    Thread {
+        inner: std_thread::current(),
+        model: super::current(),
+    }
+}
+
+pub fn park() {
+    let Some(Handle { execution, id }) = super::current() else {
+        return std_thread::park();
+    };
+
+    let mut state = execution.yield_point(id);
+    if !std::mem::take(&mut state.threads[id].has_unpark_token) {
+        state = execution.block(state, id, Status::Parked);
+    }
+    acquire_unparks(&mut state, id);
+}
+
+/// Everything the unparking threads did before [Thread::unpark] happens-before park returns.
+fn acquire_unparks(state: &mut State, me: usize) {
+    let thread = &mut state.threads[me];
+    let clock = std::mem::take(&mut thread.unpark_clock);
+    thread.clock.join(&clock);
+}
+
+/// Yields in a model execution: the timeout "expires" once the other threads are stuck.
+pub fn park_timeout(timeout: Duration) {
+    let Some(Handle { execution, id }) = super::current() else {
+        return std_thread::park_timeout(timeout);
+    };
+
+    let mut state = execution.yield_point(id);
+    if !std::mem::take(&mut state.threads[id].has_unpark_token) {
+        drop(state);
+        yield_now();
+        // an unpark while yielding ends the timeout early
+        state = execution.lock();
+        state.threads[id].has_unpark_token = false;
+    }
+    acquire_unparks(&mut state, id);
+}
+
+/// In a model execution, the current thread doesn't run again until the other threads made progress (or are stuck too).
+pub fn yield_now() {
+    match super::current() {
+        Some(Handle { execution, id }) => execution.yield_now(id),
+        None => std_thread::yield_now(),
+    }
+}
+
+/// Yields in a model execution, see [park_timeout].
+pub fn sleep(duration: Duration) {
+    match super::current() {
+        Some(_) => yield_now(),
+        None => std_thread::sleep(duration),
+    }
+}
+
+/// Not modeled: threads of a scope would run outside of the execution.
+/// # Panics
+/// - When called in a model execution. Use [spawn] instead
+pub fn scope<'env, F, T>(f: F) -> T
+where
+    F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> T,
+{
+    assert!(
+        super::current().is_none(),
+        "thread::scope is not supported in a model execution, use thread::spawn"
+    );
+    std_thread::scope(f)
+}
+
+pub fn spawn<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let Some(Handle { execution, id: me }) = super::current() else {
+        return JoinHandle(Inner::Std(std_thread::spawn(f)));
+    };
+
+    let mut state = execution.lock();
+    // everything the spawning thread did happens-before the new thread
+    state.threads[me].clock.tick(me);
+    let clock = state.threads[me].clock.clone();
+    let id = state.threads.len();
+    state.threads.push(super::ThreadState::new(clock));
+    drop(state);
+
+    let result = Arc::new(StdMutex::new(None));
+    execution.start_thread(
+        id,
+        Box::new({
+            let result = Arc::clone(&result);
+            move || {
+                let value = f();
+                *result.lock().unwrap_or_else(PoisonError::into_inner) = Some(value);
+            }
+        }),
+    );
+
+    JoinHandle(Inner::Model {
+        handle: Handle { execution, id },
+        result,
+    })
+}
+
+pub struct JoinHandle<T>(Inner<T>);
+
+enum Inner<T> {
+    Std(std_thread::JoinHandle<T>),
+    Model {
+        handle: Handle,
+        result: Arc<StdMutex<Option<T>>>,
+    },
+}
+
+impl<T> JoinHandle<T> {
+    /// In a model execution a panicking thread fails the whole execution, so this never returns an `Err` there.
+    pub fn join(self) -> Result<T> {
+        let (target, result) = match self.0 {
+            Inner::Std(handle) => return handle.join(),
+            Inner::Model { handle, result } => (handle, result),
+        };
+        let me = super::current()
+            .expect("a model thread can only be joined by a thread of the same execution")
+            .id;
+
+        let execution = &target.execution;
+        let mut state = execution.lock();
+        if state.threads[target.id].status != Status::Finished {
+            state = execution.block(state, me, Status::Joining(target.id));
+        }
+
+        // everything the finished thread did happens-before join returns
+        let clock = state.threads[target.id].clock.clone();
+        state.threads[me].clock.join(&clock);
+        drop(state);
+
+        let value = result.lock().unwrap_or_else(PoisonError::into_inner).take();
+        // only missing when the execution is aborted
+        value.ok_or_else(|| Box::new("the model execution was aborted") as Box<_>)
+    }
+}